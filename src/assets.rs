@@ -0,0 +1,704 @@
+use crate::Result;
+use crate::event::Event;
+use crate::fsutil;
+use crate::hash;
+use crate::remote::{Download, MAX_RETRY, RemoteSource, is_unsupported, retry_order};
+use crate::scores::MirrorScores;
+use crate::version;
+use mlua::ChunkMode;
+use rayon::prelude::*;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+pub(crate) const ASSETS_DIR: &str = "_assets";
+pub(crate) const TRASHED_DIR: &str = "_trashed_assets";
+pub(crate) const ASSETS_INDEX: &str = "assets_index.lua";
+// 未下载完成的资源的后缀
+const PART_SUFFIX: &str = ".part";
+
+const SPEED_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const MIN_SPEED: u64 = 10 * 1024; // 10KB/s
+
+// assets_index.lua 中的一条记录。sha256 是可选字段，旧索引没有时只按大小判断
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetEntry {
+    pub size: u64,
+    pub sha256: Option<String>,
+}
+
+impl AssetEntry {
+    // 本地文件是否与索引一致：大小不同直接判为过期，大小相同且有哈希时再比对内容
+    pub fn matches(&self, path: &Path) -> bool {
+        if !path.is_file() || file_size(path) != self.size {
+            return false;
+        }
+        match &self.sha256 {
+            Some(expected) => {
+                hash::sha256_file(path).is_ok_and(|h| h.eq_ignore_ascii_case(expected))
+            }
+            None => true,
+        }
+    }
+}
+
+// 单个资源的下载结果
+#[derive(Debug, Clone)]
+pub struct AssetOutcome {
+    pub file: String,
+    pub size: u64,
+    // 实际尝试的次数
+    pub attempts: u64,
+    // 最后一个成功传输数据的镜像
+    pub mirror: Option<String>,
+    // 失败原因，成功时为 None
+    pub error: Option<String>,
+}
+
+impl AssetOutcome {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+// 一次资源同步的结果
+#[derive(Debug, Clone, Default)]
+pub struct AssetReport {
+    pub files: Vec<AssetOutcome>,
+    pub trashed: Vec<String>,
+}
+
+impl AssetReport {
+    pub fn is_complete(&self) -> bool {
+        self.files.iter().all(AssetOutcome::is_success)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &AssetOutcome> {
+        self.files.iter().filter(|outcome| !outcome.is_success())
+    }
+}
+
+// 预演时计算出的资源变动，不下载也不移动任何文件
+#[derive(Debug, Clone, Default)]
+pub struct AssetPlan {
+    // release 标签 → 需要下载的资源及其大小
+    pub downloads: BTreeMap<String, Vec<(String, u64)>>,
+    // 将被移入回收站的多余资源
+    pub trash: Vec<String>,
+}
+
+impl AssetPlan {
+    pub fn count(&self) -> usize {
+        self.downloads.values().map(Vec::len).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.downloads
+            .values()
+            .flatten()
+            .map(|(_, size)| size)
+            .sum()
+    }
+}
+
+// 需要下载的资源，按所在 release 分批
+pub(crate) type DownloadBatches = HashMap<String, Vec<(String, AssetEntry)>>;
+
+pub(crate) fn update_assets(
+    root: &Path,
+    sources: &[Arc<dyn RemoteSource>],
+    scores: &MirrorScores,
+    emit: &(dyn Fn(Event) + Sync),
+) -> Result<AssetReport> {
+    let assets_dir = root.join(ASSETS_DIR);
+    let trashed_dir = root.join(TRASHED_DIR);
+    let assets_index = read_assets_index(assets_dir.join(ASSETS_INDEX))?;
+
+    let batches = plan_downloads(&assets_index, &assets_dir, emit);
+    let files = download_batches(&batches, &assets_dir, sources, scores, emit);
+    let trashed = trash_unindexed_assets(&assets_index, &assets_dir, &trashed_dir, emit)?;
+
+    Ok(AssetReport { files, trashed })
+}
+
+// 找出 assets_dir 中缺失或与索引不符的资源，按 release 分批
+pub(crate) fn plan_downloads(
+    assets_index: &HashMap<String, AssetEntry>,
+    assets_dir: &Path,
+    emit: &(dyn Fn(Event) + Sync),
+) -> DownloadBatches {
+    let stale = stale_assets(assets_index, assets_dir);
+    // 资源已是最新时，残留的未完成下载不再需要
+    let stale_paths: HashSet<&String> = stale.iter().map(|(path, _)| *path).collect();
+    for path in assets_index.keys() {
+        if !stale_paths.contains(path) {
+            let _ = fs::remove_file(part_path(&assets_dir.join(path)));
+        }
+    }
+
+    emit(Event::AssetsPlanned { count: stale.len() });
+    group_by_release(&stale)
+}
+
+// 只读地预演一次资源同步
+pub(crate) fn preview(
+    assets_index: &HashMap<String, AssetEntry>,
+    assets_dir: &Path,
+) -> Result<AssetPlan> {
+    let mut downloads = BTreeMap::new();
+    for (release, files) in group_by_release(&stale_assets(assets_index, assets_dir)) {
+        let mut files: Vec<(String, u64)> = files
+            .into_iter()
+            .map(|(file, entry)| (file, entry.size))
+            .collect();
+        files.sort();
+        downloads.insert(release, files);
+    }
+    let trash = if assets_dir.is_dir() {
+        unindexed_assets(assets_index, assets_dir)?
+    } else {
+        Vec::new()
+    };
+    Ok(AssetPlan { downloads, trash })
+}
+
+// 缺失或与索引不符的资源。需要计算哈希时并行处理
+fn stale_assets<'a>(
+    assets_index: &'a HashMap<String, AssetEntry>,
+    assets_dir: &Path,
+) -> Vec<(&'a String, &'a AssetEntry)> {
+    assets_index
+        .par_iter()
+        .filter(|(path, entry)| !entry.matches(&assets_dir.join(path)))
+        .collect()
+}
+
+fn group_by_release(stale: &[(&String, &AssetEntry)]) -> DownloadBatches {
+    let mut download_batches = DownloadBatches::new();
+    for (path, entry) in stale {
+        let filename = Path::new(path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(path);
+        let release = get_release_for_file(filename);
+        download_batches
+            .entry(release)
+            .or_default()
+            .push(((*path).clone(), (*entry).clone()));
+    }
+
+    download_batches
+}
+
+// 把各批资源下载到 dest_dir 下的对应路径，每个 release 一个线程
+pub(crate) fn download_batches(
+    batches: &DownloadBatches,
+    dest_dir: &Path,
+    sources: &[Arc<dyn RemoteSource>],
+    scores: &MirrorScores,
+    emit: &(dyn Fn(Event) + Sync),
+) -> Vec<AssetOutcome> {
+    let outcomes = Mutex::new(Vec::new());
+
+    std::thread::scope(|s| {
+        for (release, files) in batches {
+            let outcomes = &outcomes;
+            s.spawn(move || {
+                files.par_iter().for_each(|(file, entry)| {
+                    let task = AssetTask {
+                        file,
+                        fullpath: dest_dir.join(file),
+                        expected_size: entry.size,
+                        expected_sha256: entry.sha256.as_deref(),
+                        scores,
+                        emit,
+                    };
+                    let outcome = task.download(sources, release);
+                    outcomes.lock().unwrap().push(outcome);
+                });
+            });
+        }
+    });
+
+    outcomes.into_inner().unwrap()
+}
+
+struct AssetTask<'a> {
+    file: &'a str,
+    fullpath: PathBuf,
+    expected_size: u64,
+    expected_sha256: Option<&'a str>,
+    scores: &'a MirrorScores,
+    emit: &'a (dyn Fn(Event) + Sync),
+}
+
+impl AssetTask<'_> {
+    // 依次尝试各个镜像下载单个资源。
+    // 数据先写入 <file>.part，中断后（包括换镜像后、下次运行时）用 Range 续传，
+    // 大小与索引一致且哈希（若索引提供）校验通过时才改名为正式文件
+    fn download(&self, sources: &[Arc<dyn RemoteSource>], release: &str) -> AssetOutcome {
+        let file = self.file;
+        let filename = Path::new(file)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(file);
+        let url_filename = release_asset_name(filename);
+        let part_path = part_path(&self.fullpath);
+        if let Some(parent) = self.fullpath.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        (self.emit)(Event::AssetStarted {
+            file: file.to_string(),
+            size: self.expected_size,
+        });
+        let mut outcome = AssetOutcome {
+            file: file.to_string(),
+            size: self.expected_size,
+            attempts: 0,
+            mirror: None,
+            error: None,
+        };
+        // 上次运行已暂存完整的资源直接复用，不再下载
+        if file_size(&self.fullpath) == self.expected_size
+            && self.fullpath.is_file()
+            && self.verify(&self.fullpath).is_ok()
+        {
+            (self.emit)(Event::AssetFinished {
+                file: file.to_string(),
+                error: None,
+            });
+            return outcome;
+        }
+        let mut last_err = None;
+        let mut complete = false;
+        for source in retry_order(sources) {
+            if outcome.attempts >= MAX_RETRY {
+                break;
+            }
+            // 已下载的部分超出索引大小，说明已损坏，从头开始
+            let mut offset = file_size(&part_path);
+            if offset > self.expected_size {
+                let _ = fs::remove_file(&part_path);
+                offset = 0;
+            }
+            if offset == self.expected_size && part_path.exists() {
+                // 上次已下载完整但还没改名，校验通过即可直接使用
+                match self.verify(&part_path) {
+                    Ok(()) => {
+                        complete = true;
+                        break;
+                    }
+                    Err(e) => {
+                        let _ = fs::remove_file(&part_path);
+                        last_err = Some(e.to_string());
+                        offset = 0;
+                    }
+                }
+            }
+            let result = source.fetch_asset(release, &url_filename, offset);
+            if matches!(&result, Err(e) if is_unsupported(e)) {
+                continue;
+            }
+            if outcome.attempts > 0 {
+                (self.emit)(Event::AssetRetry {
+                    file: file.to_string(),
+                    mirror: source.name().to_string(),
+                    attempt: outcome.attempts + 1,
+                    max: MAX_RETRY,
+                });
+            }
+            outcome.attempts += 1;
+            match result
+                .and_then(|download| self.receive(download, source.name(), &part_path, offset))
+                .and_then(|()| {
+                    self.verify(&part_path).inspect_err(|_| {
+                        // 内容已损坏，续传也无济于事
+                        let _ = fs::remove_file(&part_path);
+                    })
+                }) {
+                Ok(()) => {
+                    complete = true;
+                    self.scores.record_success(source.name());
+                    outcome.mirror = Some(source.name().to_string());
+                    break;
+                }
+                Err(e) => {
+                    // 请求失败、状态异常或传输不完整，换下一个镜像
+                    self.scores.record_failure(source.name());
+                    (self.emit)(Event::AssetRequestFailed {
+                        file: file.to_string(),
+                        mirror: source.name().to_string(),
+                        reason: e.to_string(),
+                    });
+                    last_err = Some(e.to_string());
+                }
+            }
+        }
+
+        let error = if complete {
+            fsutil::persist(&part_path, &self.fullpath)
+                .err()
+                .map(|e| format!("写入失败: {}: {:?}", file, e))
+        } else {
+            Some(last_err.unwrap_or_else(|| format!("请求失败: {}", file)))
+        };
+        (self.emit)(Event::AssetFinished {
+            file: file.to_string(),
+            error: error.clone(),
+        });
+        outcome.error = error;
+        outcome
+    }
+
+    // 校验已下载完整的文件的 SHA-256，索引没有哈希时跳过
+    fn verify(&self, part_path: &Path) -> Result<()> {
+        let Some(expected) = self.expected_sha256 else {
+            return Ok(());
+        };
+        let actual = hash::sha256_file(part_path)
+            .map_err(|e| format!("读取失败: {}: {:?}", self.file, e))?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(format!("SHA-256 校验失败：期望 {}，实际 {}", expected, actual).into());
+        }
+        Ok(())
+    }
+
+    // 把一次响应写入 .part。只有收到的数据与 Content-Length 及索引大小都吻合才算成功；
+    // 连接提前断开时保留已收到的部分供续传，远程文件与索引不符时丢弃
+    fn receive(
+        &self,
+        mut download: Download,
+        mirror: &str,
+        part_path: &Path,
+        offset: u64,
+    ) -> Result<()> {
+        // 服务器不支持 Range 时会从头返回整个文件
+        let start = if download.resumed { offset } else { 0 };
+        if let Some(len) = download.content_length
+            && start + len != self.expected_size
+        {
+            return Err(format!(
+                "远程文件大小 {} 与索引记录 {} 不符",
+                start + len,
+                self.expected_size
+            )
+            .into());
+        }
+
+        let mut file_out = if download.resumed {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(part_path)
+        } else {
+            fs::File::create(part_path)
+        }
+        .map_err(|e| format!("写入失败: {}: {:?}", self.file, e))?;
+
+        let started = std::time::Instant::now();
+        let mut downloaded: u64 = start;
+        let mut buf = [0u8; 16 * 1024];
+        let mut last_check = std::time::Instant::now();
+        let mut last_downloaded = start;
+        let mut slow_count = 0;
+        let result: Result<()> = loop {
+            match download.reader.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(n) => {
+                    if let Err(e) = file_out.write_all(&buf[..n]) {
+                        break Err(format!("写入失败: {}: {:?}", self.file, e).into());
+                    }
+                    downloaded += n as u64;
+                    (self.emit)(Event::AssetProgress {
+                        file: self.file.to_string(),
+                        downloaded,
+                    });
+                    if downloaded > self.expected_size {
+                        break Err("下载内容超出索引记录的大小".into());
+                    }
+                    let now = std::time::Instant::now();
+                    if now.duration_since(last_check) >= SPEED_CHECK_INTERVAL {
+                        let bytes = downloaded - last_downloaded;
+                        let speed = bytes / SPEED_CHECK_INTERVAL.as_secs();
+                        if speed < MIN_SPEED {
+                            slow_count += 1;
+                        } else {
+                            slow_count = 0;
+                        }
+                        last_check = now;
+                        last_downloaded = downloaded;
+                        if slow_count >= 2 {
+                            (self.emit)(Event::AssetSlow {
+                                file: self.file.to_string(),
+                            });
+                            // 主动中断，换下一个镜像续传
+                            break Err("速度过慢，切换镜像".into());
+                        }
+                    }
+                }
+                Err(e) => break Err(format!("下载中断: {:?}", e).into()),
+            }
+        };
+        drop(file_out);
+        self.scores
+            .record_transfer(mirror, downloaded - start, started.elapsed());
+
+        if downloaded > self.expected_size {
+            let _ = fs::remove_file(part_path);
+        }
+        result?;
+        if downloaded != self.expected_size {
+            return Err(format!(
+                "连接提前断开，只收到 {}/{} 字节",
+                downloaded, self.expected_size
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    fsutil::sibling_with_suffix(path, PART_SUFFIX)
+}
+
+pub fn read_assets_index(path: impl AsRef<Path>) -> Result<HashMap<String, AssetEntry>> {
+    parse_assets_index(&std::fs::read_to_string(path)?)
+}
+
+// 索引随代码一同从远程下载，在受限的运行时中执行。索引列出所有资源，限制比 version.lua 宽松
+const INDEX_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+const INDEX_MAX_INSTRUCTIONS: u32 = 50_000_000;
+
+pub(crate) fn parse_assets_index(content: &str) -> Result<HashMap<String, AssetEntry>> {
+    let lua = version::sandbox(ASSETS_INDEX, INDEX_MEMORY_LIMIT, INDEX_MAX_INSTRUCTIONS)?;
+    let table: mlua::Table = lua.load(content).set_mode(ChunkMode::Text).eval()?;
+
+    let mut index = HashMap::new();
+    for pair in table.pairs::<String, mlua::Table>() {
+        let (key, value_table) = pair?;
+        let key = fsutil::safe_relative_path(&key)?;
+        let size: u64 = value_table.get("size")?;
+        let sha256: Option<String> = value_table.get("sha256")?;
+        index.insert(key, AssetEntry { size, sha256 });
+    }
+    Ok(index)
+}
+
+pub(crate) fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+}
+
+fn get_release_for_file(filename: &str) -> String {
+    // 去除扩展名（最后一个点及其后内容）
+    let name = match filename.rfind('.') {
+        Some(idx) => &filename[..idx],
+        None => filename,
+    };
+    let len = name.chars().count();
+    if len == 0 {
+        return "other".to_string();
+    }
+    // Lua: local mid = math.floor((len + 1) / 2)
+    let mid = len.div_ceil(2) - 1; // Rust 0-based
+    let ch = name.chars().nth(mid).unwrap_or('o').to_ascii_lowercase();
+    if ch.is_ascii_alphanumeric() {
+        ch.to_string()
+    } else {
+        "other".to_string()
+    }
+}
+
+// GitHub release 会把附件名中的括号、引号和空格替换为点
+fn release_asset_name(filename: &str) -> String {
+    static RE_SQUARE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([^\]]+)\]").unwrap());
+    static RE_ROUND: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\(([^)]+)\)").unwrap());
+    static RE_DOT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\.+").unwrap());
+
+    let replaced =
+        RE_SQUARE.replace_all(filename, |caps: &regex::Captures| format!(".{}.", &caps[1]));
+    let replaced = RE_ROUND.replace_all(&replaced, |caps: &regex::Captures| {
+        format!(".{}.", &caps[1])
+    });
+    let replaced = replaced.replace("'", ".");
+    let replaced = replaced.replace(" ", ".");
+    RE_DOT.replace_all(&replaced, ".").into_owned()
+}
+
+// assets_dir 顶层中不在索引里的文件（索引本身和未完成的下载除外）
+pub(crate) fn unindexed_assets(
+    index: &HashMap<String, AssetEntry>,
+    assets_dir: &Path,
+) -> Result<Vec<String>> {
+    let mut unindexed = Vec::new();
+    for entry in fs::read_dir(assets_dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            let relpath = path
+                .strip_prefix(assets_dir)?
+                .to_str()
+                .unwrap_or("")
+                .to_string();
+            if relpath != ASSETS_INDEX
+                && !relpath.ends_with(PART_SUFFIX)
+                && !index.contains_key(&relpath)
+            {
+                unindexed.push(relpath);
+            }
+        }
+    }
+    unindexed.sort();
+    Ok(unindexed)
+}
+
+pub(crate) fn trash_unindexed_assets(
+    index: &HashMap<String, AssetEntry>,
+    assets_dir: &Path,
+    trashed_dir: &Path,
+    emit: &(dyn Fn(Event) + Sync),
+) -> Result<Vec<String>> {
+    let trashed = unindexed_assets(index, assets_dir)?;
+    for relpath in &trashed {
+        let trash_path = trashed_dir.join(relpath);
+        if let Some(parent) = trash_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(assets_dir.join(relpath), &trash_path)?;
+        emit(Event::AssetTrashed {
+            path: trash_path.display().to_string(),
+        });
+    }
+    Ok(trashed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("krdove-assets-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn download(content: &[u8], content_length: Option<u64>, resumed: bool) -> Download {
+        Download {
+            reader: Box::new(Cursor::new(content.to_vec())),
+            content_length,
+            resumed,
+        }
+    }
+
+    fn receive(dir: &Path, existing: Option<&[u8]>, offset: u64, download: Download) -> Result<()> {
+        let part = dir.join("a.png.part");
+        if let Some(existing) = existing {
+            fs::write(&part, existing).unwrap();
+        }
+        let scores = MirrorScores::load(dir.join(crate::scores::SCORES_FILE));
+        let task = AssetTask {
+            file: "a.png",
+            fullpath: dir.join("a.png"),
+            expected_size: 5,
+            expected_sha256: None,
+            scores: &scores,
+            emit: &|_| {},
+        };
+        task.receive(download, "mirror", &part, offset)
+    }
+
+    #[test]
+    fn receive_appends_resumed_download() {
+        let dir = temp_dir("resumed");
+        receive(&dir, Some(b"he"), 2, download(b"llo", Some(3), true)).unwrap();
+        assert_eq!(fs::read(dir.join("a.png.part")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn receive_restarts_when_range_is_ignored() {
+        let dir = temp_dir("restart");
+        receive(&dir, Some(b"xx"), 2, download(b"hello", Some(5), false)).unwrap();
+        assert_eq!(fs::read(dir.join("a.png.part")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn receive_keeps_partial_data_for_resume() {
+        let dir = temp_dir("partial");
+        assert!(receive(&dir, None, 0, download(b"hel", None, false)).is_err());
+        assert_eq!(fs::read(dir.join("a.png.part")).unwrap(), b"hel");
+    }
+
+    #[test]
+    fn receive_writes_complete_download() {
+        let dir = temp_dir("complete");
+        receive(&dir, None, 0, download(b"hello", Some(5), false)).unwrap();
+        assert_eq!(fs::read(dir.join("a.png.part")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn receive_rejects_wrong_content_length() {
+        let dir = temp_dir("length");
+        assert!(receive(&dir, Some(b"he"), 2, download(b"hello!", Some(6), false)).is_err());
+        // 没有开始写入，已下载的部分保留
+        assert_eq!(fs::read(dir.join("a.png.part")).unwrap(), b"he");
+    }
+
+    #[test]
+    fn receive_discards_oversized_data() {
+        let dir = temp_dir("oversized");
+        assert!(receive(&dir, None, 0, download(b"hello world", None, false)).is_err());
+        assert!(!dir.join("a.png.part").exists());
+    }
+
+    #[test]
+    fn download_reuses_verified_file() {
+        let dir = temp_dir("reuse");
+        fs::write(dir.join("a.png"), b"hello").unwrap();
+        let scores = MirrorScores::load(dir.join(crate::scores::SCORES_FILE));
+        let task = |sha256| AssetTask {
+            file: "a.png",
+            fullpath: dir.join("a.png"),
+            expected_size: 5,
+            expected_sha256: sha256,
+            scores: &scores,
+            emit: &|_| {},
+        };
+        // 没有可用的镜像，只有复用已有文件时才会成功
+        let sha256 = hash::sha256_file(&dir.join("a.png")).unwrap();
+        assert_eq!(task(Some(&sha256)).download(&[], "a").error, None);
+        let wrong = "0".repeat(64);
+        assert!(task(Some(&wrong)).download(&[], "a").error.is_some());
+    }
+
+    #[test]
+    fn assets_index_rejects_unsafe_keys() {
+        for key in [
+            "../evil.png",
+            "/evil.png",
+            "C:evil.png",
+            "a\\..\\..\\evil.png",
+        ] {
+            let index = format!("return {{ [{key:?}] = {{ size = 1 }} }}");
+            assert!(parse_assets_index(&index).is_err(), "{key:?}");
+        }
+        let index = parse_assets_index(r#"return { ["a.png"] = { size = 1 } }"#).unwrap();
+        assert_eq!(index["a.png"].size, 1);
+    }
+
+    #[test]
+    fn assets_index_runs_without_file_access() {
+        for chunk in [
+            "return { [io.read()] = { size = 1 } }",
+            "os.remove('version.lua') return {}",
+            "while true do end",
+        ] {
+            assert!(parse_assets_index(chunk).is_err(), "{chunk}");
+        }
+    }
+}
@@ -0,0 +1,303 @@
+// 更新备份。每次更新把被覆盖或删除的文件移入 _updater/backups/<旧 commit>/files/，
+// 并在 manifest.json 中记录本次新增与移入回收站的文件，回滚时据此还原。
+// manifest 在移动任何文件之前写入，移动中途失败时也能据此恢复；
+// 同一旧版本已有备份时使用 <旧 commit>-<时间戳> 目录，不覆盖已有的备份。
+
+use crate::Result;
+use crate::fsutil;
+use serde_json::{Value, json};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const BACKUPS_DIR: &str = "backups";
+const MANIFEST_FILE: &str = "manifest.json";
+const FILES_DIR: &str = "files";
+
+#[derive(Debug, Clone)]
+pub struct BackupInfo {
+    // 备份目录名
+    pub id: String,
+    // 更新前的版本，即回滚后的版本
+    pub from_commit: String,
+    pub to_commit: String,
+    // 更新前后 version.lua 中的游戏版本号
+    pub from_version: Option<String>,
+    pub to_version: Option<String>,
+    // 创建时间，unix 时间戳
+    pub created: u64,
+    // 创建顺序，比已有备份的最大值大 1。同一秒内的多个备份按它区分先后，旧版本的备份为 0
+    pub sequence: u64,
+    // 被覆盖或删除而备份下来的文件
+    pub saved: Vec<String>,
+    // 本次更新新增的文件，回滚时删除
+    pub added: Vec<String>,
+    // 本次更新移入回收站的资源，相对 _assets
+    pub trashed: Vec<String>,
+}
+
+impl BackupInfo {
+    // 修复不改变版本，其备份只保留被替换的原文件，不能用于回滚
+    pub fn is_repair(&self) -> bool {
+        self.from_commit == self.to_commit
+    }
+
+    fn from_json(id: String, j: &Value) -> Option<Self> {
+        let strings = |key: &str| -> Vec<String> {
+            j[key]
+                .as_array()
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Some(BackupInfo {
+            id,
+            from_commit: j["from"].as_str()?.to_string(),
+            to_commit: j["to"].as_str()?.to_string(),
+            from_version: j["from_version"].as_str().map(str::to_string),
+            to_version: j["to_version"].as_str().map(str::to_string),
+            created: j["created"].as_u64().unwrap_or(0),
+            sequence: j["sequence"].as_u64().unwrap_or(0),
+            saved: strings("saved"),
+            added: strings("added"),
+            trashed: strings("trashed"),
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "from": self.from_commit,
+            "to": self.to_commit,
+            "from_version": self.from_version,
+            "to_version": self.to_version,
+            "created": self.created,
+            "sequence": self.sequence,
+            "saved": self.saved,
+            "added": self.added,
+            "trashed": self.trashed,
+        })
+    }
+}
+
+// 正在进行中的一次更新的备份
+pub(crate) struct Backup {
+    dir: PathBuf,
+    root: PathBuf,
+    info: BackupInfo,
+}
+
+impl Backup {
+    pub(crate) fn begin(
+        backups_dir: &Path,
+        root: &Path,
+        from_commit: &str,
+        to_commit: &str,
+    ) -> io::Result<Self> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let sequence = list(backups_dir)
+            .iter()
+            .map(|b| b.sequence + 1)
+            .max()
+            .unwrap_or(1);
+        let mut id = from_commit.to_string();
+        let mut n = 0;
+        while backups_dir.join(&id).exists() {
+            n += 1;
+            id = format!("{from_commit}-{created}-{n}");
+        }
+        let dir = backups_dir.join(&id);
+        fs::create_dir_all(&dir)?;
+        Ok(Backup {
+            dir,
+            root: root.to_path_buf(),
+            info: BackupInfo {
+                id,
+                from_commit: from_commit.to_string(),
+                to_commit: to_commit.to_string(),
+                from_version: None,
+                to_version: None,
+                created,
+                sequence,
+                saved: Vec::new(),
+                added: Vec::new(),
+                trashed: Vec::new(),
+            },
+        })
+    }
+
+    // 登记将被覆盖或删除的 file（相对游戏目录）：现在存在的记为需备份，否则记为新增。
+    // 所有文件登记完并写入 manifest 后才开始移动。同一文件只登记一次
+    pub(crate) fn record(&mut self, file: &str) {
+        if self
+            .info
+            .saved
+            .iter()
+            .chain(&self.info.added)
+            .any(|f| f == file)
+        {
+            return;
+        }
+        if self.root.join(file).is_file() {
+            self.info.saved.push(file.to_string());
+        } else {
+            self.info.added.push(file.to_string());
+        }
+    }
+
+    // 在 file 被覆盖或删除前调用，存在则移入备份
+    pub(crate) fn save(&self, file: &str) -> io::Result<()> {
+        let path = self.root.join(file);
+        if path.is_file() {
+            let saved = self.dir.join(FILES_DIR).join(file);
+            if let Some(parent) = saved.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&path, &saved)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn info(&self) -> &BackupInfo {
+        &self.info
+    }
+
+    pub(crate) fn record_versions(&mut self, from: Option<String>, to: Option<String>) {
+        self.info.from_version = from;
+        self.info.to_version = to;
+    }
+
+    pub(crate) fn record_trashed(&mut self, trashed: &[String]) {
+        self.info.trashed.extend_from_slice(trashed);
+    }
+
+    pub(crate) fn write_manifest(&self) -> Result<()> {
+        fsutil::write_atomic(
+            &self.dir.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(&self.info.to_json())?.as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+// 列出所有完整的备份，最新的在前
+pub(crate) fn list(backups_dir: &Path) -> Vec<BackupInfo> {
+    let Ok(entries) = fs::read_dir(backups_dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<BackupInfo> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let content = fs::read_to_string(entry.path().join(MANIFEST_FILE)).ok()?;
+            let id = entry.file_name().to_str()?.to_string();
+            BackupInfo::from_json(id, &serde_json::from_str(&content).ok()?)
+        })
+        .collect();
+    backups.sort_by_key(|b| std::cmp::Reverse((b.sequence, b.created)));
+    backups
+}
+
+// 回滚 commit 这次更新所用的备份，即最近一次更新到 commit 的备份
+pub(crate) fn find_rollback(backups_dir: &Path, commit: &str) -> Option<BackupInfo> {
+    list(backups_dir)
+        .into_iter()
+        .find(|b| b.to_commit == commit && !b.is_repair())
+}
+
+// 只保留最近 keep 次更新与 keep 次修复的备份，修复的备份不会挤掉更新的备份。
+// 没有 manifest 的目录只在为空时删除，其中可能有来不及登记的原文件
+pub(crate) fn prune(backups_dir: &Path, keep: usize) {
+    let (repairs, updates): (Vec<_>, Vec<_>) = list(backups_dir)
+        .into_iter()
+        .partition(BackupInfo::is_repair);
+    for info in updates
+        .into_iter()
+        .skip(keep)
+        .chain(repairs.into_iter().skip(keep))
+    {
+        let _ = fs::remove_dir_all(backups_dir.join(&info.id));
+    }
+    let Ok(entries) = fs::read_dir(backups_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if !entry.path().join(MANIFEST_FILE).exists() {
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+}
+
+// 把游戏目录还原到备份记录的更新之前的状态
+pub(crate) fn restore(
+    backups_dir: &Path,
+    root: &Path,
+    assets_dir: &Path,
+    trashed_dir: &Path,
+    info: &BackupInfo,
+) -> Result<()> {
+    let dir = backups_dir.join(&info.id);
+    for file in &info.added {
+        let path = root.join(file);
+        if path.is_file() {
+            fs::remove_file(&path)?;
+        }
+    }
+    for file in &info.saved {
+        let saved = dir.join(FILES_DIR).join(file);
+        // 更新中途失败时，尚未移入备份的文件仍在原处
+        if !saved.is_file() {
+            continue;
+        }
+        let path = root.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&saved, &path)?;
+    }
+    for file in &info.trashed {
+        let trashed = trashed_dir.join(file);
+        if trashed.is_file() {
+            let path = assets_dir.join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&trashed, &path)?;
+        }
+    }
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("krdove-backup-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn list_orders_backups_from_the_same_second_by_sequence() {
+        let dir = temp_dir("sequence");
+        let backups_dir = dir.join(BACKUPS_DIR);
+        for (from, to) in [("a", "b"), ("b", "a"), ("a", "b")] {
+            let backup = Backup::begin(&backups_dir, &dir, from, to).unwrap();
+            backup.write_manifest().unwrap();
+        }
+        let backups = list(&backups_dir);
+        let sequences: Vec<_> = backups.iter().map(|b| b.sequence).collect();
+        assert_eq!(sequences, [3, 2, 1]);
+        // 最近一次更新到 b 的是第三个备份
+        assert_eq!(find_rollback(&backups_dir, "b").unwrap().sequence, 3);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
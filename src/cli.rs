@@ -0,0 +1,255 @@
+// 命令行参数解析。不带任何参数运行时保持原有的交互式菜单。
+
+pub const USAGE: &str = "用法: KingdomRushDoveUpdater [命令] [选项]
+
+命令:
+  update      正常更新到最新版本
+  fix         修复式更新（重新下载自原始版本以来变动的所有代码文件）
+  repair      按当前安装的版本逐个校验代码文件，只重新下载缺失或损坏的文件
+  assets      检查并更新美术资源
+  status      显示本地版本与远程最新版本
+  check       仅检查是否有新版本，不做任何修改
+  verify      检查本地安装是否完整，不做任何修改；发现问题时返回非零退出码
+  rollback    回滚最近一次更新
+  history [<数量>]
+              列出当前通道最近的提交（默认 20 个），标出已安装的版本、最新版本
+              与本地已知的游戏版本号（当前安装及备份中记录的版本），
+              并可输入序号更新到其中某个版本。选择最新以外的版本时会固定在该版本
+  channel [<通道>]
+              显示当前更新通道，或切换到指定通道（远程分支，如 master、dev）。
+              切换后下次更新会从本地已安装的版本差分到新通道的最新版本
+  mirrors bench
+              测试所有镜像的可用性与延迟并排序
+
+选项:
+  -y, --yes         自动确认所有提示
+  --no-pause        结束时不等待回车
+  --json            以逐行 JSON 事件输出，不含任何颜色与进度条，供启动器等程序解析。
+                    隐含 --yes 与 --no-pause，不带命令时执行 update
+  --dry-run         只显示将要进行的更新（提交范围、文件变动、需下载的资源、
+                    将移入回收站的资源），不写入、不移动、不下载任何文件
+  --to <版本>       更新到指定的提交或标签（可以比当前版本新或旧），并固定在该版本。
+                    之后的正常更新在离开固定版本前会先询问；仅用于 update 与 fix
+  --unpin           离开固定版本，更新到当前通道的最新版本而不再询问。
+                    使用 --yes 或 --json 时若不指定此选项，将保持固定版本
+  --no-self-update  不检查新版更新程序。默认 update、fix、repair、assets 与交互式菜单
+                    运行前会检查，有新版本时下载并校验，替换自身后以相同参数重新启动；
+                    更新程序清单须带有效的 minisign 签名，未内置签名公钥的构建
+                    以及 --dry-run 时不检查
+  --source <地址>   使用指定的远程源代替内置镜像，可重复指定，按顺序尝试。
+                    地址可以是 GitHub 镜像（https://...）、gitee，
+                    或本地目录（file://... 或目录路径）。GitHub 镜像不提供差分，
+                    只指定 GitHub 镜像时仍会使用 Gitee 获取差分与文件树
+  -h, --help        显示本帮助
+
+不带任何命令运行时进入交互式菜单。
+
+退出码:
+  0  已更新，或命令正常完成
+  1  内部错误
+  2  命令行参数错误
+  3  已是最新，无需更新
+  4  有可用的新版本（check、status 与 --dry-run）
+  5  部分文件下载失败，本地版本记录未更新或资源不完整
+  6  网络不可达，所有镜像都无法连接
+  7  目录错误或安装无效（缺少版本记录，或 verify 发现文件缺失、损坏）
+
+镜像列表与保留的备份数量可在游戏目录下的 updater_config.lua 中配置，
+也可以用环境变量 KRDOVE_MIRRORS（逗号分隔）临时覆盖。
+列表中没有 gitee 时会自动在末尾加上 Gitee，用于获取差分与文件树。";
+
+const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Interactive,
+    Update,
+    Fix,
+    Repair,
+    Assets,
+    Status,
+    Check,
+    Verify,
+    Rollback,
+    History,
+    Channel,
+    MirrorsBench,
+    Help,
+}
+
+impl Command {
+    // JSON 输出中使用的命令名
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Interactive => "interactive",
+            Command::Update => "update",
+            Command::Fix => "fix",
+            Command::Repair => "repair",
+            Command::Assets => "assets",
+            Command::Status => "status",
+            Command::Check => "check",
+            Command::Verify => "verify",
+            Command::Rollback => "rollback",
+            Command::History => "history",
+            Command::Channel => "channel",
+            Command::MirrorsBench => "mirrors_bench",
+            Command::Help => "help",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub command: Command,
+    pub yes: bool,
+    pub no_pause: bool,
+    pub dry_run: bool,
+    pub json: bool,
+    pub sources: Vec<String>,
+    // channel 命令要切换到的通道，未指定时只显示当前通道
+    pub channel: Option<String>,
+    // history 命令列出的提交数量
+    pub history_limit: usize,
+    // --to 指定的目标版本
+    pub target: Option<String>,
+    pub unpin: bool,
+    pub no_self_update: bool,
+}
+
+impl Cli {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+        let mut command = None;
+        let mut yes = false;
+        let mut no_pause = false;
+        let mut dry_run = false;
+        let mut json = false;
+        let mut sources = Vec::new();
+        let mut channel = None;
+        let mut history_limit = DEFAULT_HISTORY_LIMIT;
+        let mut target = None;
+        let mut unpin = false;
+        let mut no_self_update = false;
+
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-y" | "--yes" => yes = true,
+                "--no-pause" => no_pause = true,
+                "--dry-run" => dry_run = true,
+                "--json" => json = true,
+                "--to" => target = Some(args.next().ok_or("--to 需要一个提交或标签")?),
+                "--unpin" => unpin = true,
+                "--no-self-update" => no_self_update = true,
+                "--source" => sources.push(args.next().ok_or("--source 需要一个地址")?),
+                "-h" | "--help" => command = Some(Command::Help),
+                s if s.starts_with('-') => return Err(format!("未知选项: {s}")),
+                s => {
+                    if command.is_some() {
+                        return Err(format!("多余的参数: {s}"));
+                    }
+                    command = Some(match s {
+                        "update" => Command::Update,
+                        "fix" => Command::Fix,
+                        "repair" => Command::Repair,
+                        "assets" => Command::Assets,
+                        "status" => Command::Status,
+                        "check" => Command::Check,
+                        "verify" => Command::Verify,
+                        "rollback" => Command::Rollback,
+                        "history" => {
+                            if let Some(limit) = args.next_if(|a| !a.starts_with('-')) {
+                                history_limit = limit
+                                    .parse()
+                                    .ok()
+                                    .filter(|n| *n > 0)
+                                    .ok_or(format!("无效的数量: {limit}"))?;
+                            }
+                            Command::History
+                        }
+                        "channel" => {
+                            channel = args.next_if(|a| !a.starts_with('-'));
+                            Command::Channel
+                        }
+                        "mirrors" => match args.next().as_deref() {
+                            Some("bench") => Command::MirrorsBench,
+                            Some(sub) => return Err(format!("未知命令: mirrors {sub}")),
+                            None => return Err("mirrors 需要子命令，例如 mirrors bench".into()),
+                        },
+                        "help" => Command::Help,
+                        _ => return Err(format!("未知命令: {s}")),
+                    });
+                }
+            }
+        }
+
+        // JSON 模式下没有人可以回答提示
+        let command = match command {
+            None if json => Command::Update,
+            None => Command::Interactive,
+            Some(command) => command,
+        };
+        // repair 只校验当前安装的版本
+        if target.is_some() && !matches!(command, Command::Update | Command::Fix) {
+            return Err("--to 只能用于 update 与 fix".into());
+        }
+        if unpin && command != Command::Update {
+            return Err("--unpin 只能用于 update".into());
+        }
+        if unpin && target.is_some() {
+            return Err("--unpin 不能与 --to 同时使用".into());
+        }
+        Ok(Cli {
+            command,
+            yes: yes || json,
+            no_pause: no_pause || json,
+            dry_run,
+            json,
+            sources,
+            channel,
+            history_limit,
+            target,
+            unpin,
+            no_self_update,
+        })
+    }
+
+    // 交互式菜单总是等待回车，保持原有行为
+    pub fn should_pause(&self) -> bool {
+        self.command == Command::Interactive || !self.no_pause
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_commands_and_flags() {
+        let cli = parse(&["fix", "--yes", "--no-pause"]).unwrap();
+        assert_eq!(cli.command, Command::Fix);
+        assert!(cli.yes && cli.no_pause && !cli.should_pause());
+        assert_eq!(parse(&["-y", "assets"]).unwrap().command, Command::Assets);
+        assert_eq!(parse(&["check"]).unwrap().command, Command::Check);
+        assert_eq!(parse(&["status"]).unwrap().command, Command::Status);
+    }
+
+    #[test]
+    fn defaults_to_interactive_menu() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, Command::Interactive);
+        assert!(!cli.yes);
+        // 交互式菜单即使带 --no-pause 也会等待回车
+        assert!(parse(&["--no-pause"]).unwrap().should_pause());
+    }
+
+    #[test]
+    fn rejects_unknown_arguments() {
+        assert!(parse(&["upgrade"]).is_err());
+        assert!(parse(&["update", "fix"]).is_err());
+        assert!(parse(&["update", "--force"]).is_err());
+    }
+}
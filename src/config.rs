@@ -0,0 +1,191 @@
+// 更新程序配置。游戏目录下的 updater_config.lua 返回一个表，例如：
+//
+//   return {
+//       mirrors = {
+//           { url = "https://bgithub.xyz" },
+//           { url = "https://dgithub.xyz", enabled = false },
+//       },
+//       backups = 3,
+//   }
+//
+// mirrors 的顺序即尝试顺序，url 的写法见 `source_from_url`；
+// backups 为保留最近几次更新的备份，用于回滚。
+// GitHub 镜像只提供版本、代码与资源，差分、文件树与提交记录来自 Gitee 的 API。
+// 镜像列表中没有 gitee 时会自动追加在末尾（见 `sources_from_urls`），
+// 写出 { url = "gitee" } 只用于调整它的尝试顺序。
+// 环境变量优先于配置文件：
+//   KRDOVE_UPDATER_CONFIG  配置文件路径
+//   KRDOVE_MIRRORS         以逗号分隔的镜像地址，整体替换 mirrors
+
+use crate::Result;
+use crate::remote::{RemoteSource, sources_from_urls};
+use mlua::Lua;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const CONFIG_FILE: &str = "updater_config.lua";
+const CONFIG_ENV: &str = "KRDOVE_UPDATER_CONFIG";
+const MIRRORS_ENV: &str = "KRDOVE_MIRRORS";
+const DEFAULT_BACKUPS: usize = 3;
+
+// 内置镜像：三个 GitHub 镜像负责版本、代码与资源，Gitee 由 `sources_from_urls` 追加
+const DEFAULT_MIRRORS: [&str; 3] = [
+    "https://bgithub.xyz",
+    "https://dgithub.xyz",
+    "https://hub.gitmirror.com/https://github.com",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorConfig {
+    pub url: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub mirrors: Vec<MirrorConfig>,
+    pub backups: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mirrors: DEFAULT_MIRRORS
+                .iter()
+                .map(|url| MirrorConfig {
+                    url: url.to_string(),
+                    enabled: true,
+                })
+                .collect(),
+            backups: DEFAULT_BACKUPS,
+        }
+    }
+}
+
+impl Config {
+    // 读取 root 下的配置文件（不存在时使用内置默认值），再应用环境变量
+    pub fn load(root: impl AsRef<Path>) -> Result<Config> {
+        let path = match std::env::var_os(CONFIG_ENV) {
+            Some(path) => path.into(),
+            None => root.as_ref().join(CONFIG_FILE),
+        };
+        let mut config = if path.exists() {
+            Self::from_file(&path)
+                .map_err(|e| format!("读取配置文件 {} 失败: {}", path.display(), e))?
+        } else {
+            Config::default()
+        };
+
+        if let Ok(mirrors) = std::env::var(MIRRORS_ENV) {
+            config.mirrors = parse_mirror_list(&mirrors);
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let content = std::fs::read_to_string(path)?;
+        let lua = Lua::new();
+        let table: mlua::Table = lua.load(&content).eval()?;
+
+        let mut config = Config::default();
+        if let Some(mirrors) = table.get::<_, Option<mlua::Table>>("mirrors")? {
+            config.mirrors.clear();
+            for mirror in mirrors.sequence_values::<mlua::Table>() {
+                let mirror = mirror?;
+                config.mirrors.push(MirrorConfig {
+                    url: mirror.get("url")?,
+                    enabled: mirror.get::<_, Option<bool>>("enabled")?.unwrap_or(true),
+                });
+            }
+        }
+        if let Some(backups) = table.get::<_, Option<usize>>("backups")? {
+            config.backups = backups;
+        }
+        Ok(config)
+    }
+
+    // 按配置顺序创建已启用的远程源
+    pub fn sources(&self) -> Result<Vec<Arc<dyn RemoteSource>>> {
+        let mut enabled = self
+            .mirrors
+            .iter()
+            .filter(|mirror| mirror.enabled)
+            .peekable();
+        if enabled.peek().is_none() {
+            return Err("配置中没有启用任何镜像".into());
+        }
+        sources_from_urls(enabled.map(|mirror| mirror.url.as_str()))
+    }
+}
+
+// 解析 KRDOVE_MIRRORS：以逗号分隔的镜像地址，忽略空项
+fn parse_mirror_list(mirrors: &str) -> Vec<MirrorConfig> {
+    mirrors
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| MirrorConfig {
+            url: url.to_string(),
+            enabled: true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_config(name: &str, content: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("krdove-config-{}-{name}.lua", std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn from_file_reads_mirrors_and_backups() {
+        let path = write_config(
+            "full",
+            r#"return {
+                mirrors = {
+                    { url = "https://a.example" },
+                    { url = "https://b.example", enabled = false },
+                },
+                backups = 5,
+            }"#,
+        );
+        let config = Config::from_file(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            config.mirrors,
+            vec![
+                MirrorConfig {
+                    url: "https://a.example".to_string(),
+                    enabled: true,
+                },
+                MirrorConfig {
+                    url: "https://b.example".to_string(),
+                    enabled: false,
+                },
+            ]
+        );
+        assert_eq!(config.backups, 5);
+    }
+
+    #[test]
+    fn from_file_keeps_defaults_for_missing_fields() {
+        let path = write_config("empty", "return {}");
+        let config = Config::from_file(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn mirror_list_override_splits_on_commas() {
+        let mirrors = parse_mirror_list(" https://a.example, ,gitee,");
+        let urls: Vec<_> = mirrors.iter().map(|m| m.url.as_str()).collect();
+        assert_eq!(urls, ["https://a.example", "gitee"]);
+        assert!(mirrors.iter().all(|m| m.enabled));
+    }
+}
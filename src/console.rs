@@ -0,0 +1,183 @@
+// 把更新引擎的事件渲染为彩色文本与进度条，或者在 --json 模式下输出为逐行 JSON。
+// 可执行文件的所有输出都经过这里，JSON 模式下不会出现任何 ANSI 转义序列
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use kingdom_rush_dove_updater::Event;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const RED: &str = "\x1b[31m";
+pub const CYAN: &str = "\x1b[36m";
+pub const RESET: &str = "\x1b[0m";
+
+pub struct Console {
+    json: bool,
+    multi: MultiProgress,
+    bars: Mutex<HashMap<String, ProgressBar>>,
+    // JSON 模式下每个资源的 (总大小, 上次输出的百分比)，进度只在百分比变化时输出
+    progress: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl Console {
+    pub fn new(json: bool) -> Self {
+        Console {
+            json,
+            multi: MultiProgress::new(),
+            bars: Mutex::default(),
+            progress: Mutex::default(),
+        }
+    }
+
+    // 输出一行彩色文本，JSON 模式下忽略
+    pub fn say(&self, color: &str, text: impl Display) {
+        if !self.json {
+            println!("{color}{text}{RESET}");
+        }
+    }
+
+    // 同 say，但输出到标准错误
+    pub fn warn(&self, color: &str, text: impl Display) {
+        if !self.json {
+            eprintln!("{color}{text}{RESET}");
+        }
+    }
+
+    // 输出一个 JSON 事件，文本模式下忽略
+    pub fn emit(&self, value: Value) {
+        if self.json {
+            println!("{value}");
+        }
+    }
+
+    pub fn phase(&self, phase: &str) {
+        self.emit(json!({ "event": "phase", "phase": phase }));
+    }
+
+    // 命令的最终结果。details 中的字段会合并到结果事件中
+    pub fn result(&self, command: &str, status: &str, details: Value) {
+        let mut value = json!({
+            "event": "result",
+            "command": command,
+            "status": status,
+        });
+        if let (Some(value), Value::Object(details)) = (value.as_object_mut(), details) {
+            value.extend(details);
+        }
+        self.emit(value);
+    }
+
+    pub fn handle(&self, event: Event) {
+        if self.json {
+            self.handle_json(event);
+            return;
+        }
+        match event {
+            Event::HeadRetry { mirror, reason } => {
+                println!("{RED}尝试使用镜像{mirror}获取远程版本失败，{reason}，正在重试...{RESET}");
+            }
+            Event::FileRetry {
+                file,
+                mirror,
+                reason,
+            } => {
+                eprintln!("{YELLOW}下载失败: {mirror} {file} {reason}{RESET}，已为您重试");
+            }
+            // 代码文件小而多，文本模式下不逐个显示，失败的文件在最后汇总
+            Event::FileStarted { .. } | Event::FileFinished { .. } => {}
+            Event::AssetsPlanned { count } => {
+                println!("{CYAN}需要下载或更新的美术资源数量: {count} 个{RESET}");
+            }
+            Event::AssetStarted { file, size } => {
+                let pb = self.multi.add(
+                    ProgressBar::new(size).with_style(
+                        ProgressStyle::default_bar()
+                            .template("{spinner:.green} {msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} {percent}%")
+                            .unwrap()
+                            .progress_chars("==-"),
+                    ),
+                );
+                pb.set_message(format!("下载中: {file}"));
+                self.bars.lock().unwrap().insert(file, pb);
+            }
+            Event::AssetProgress { file, downloaded } => {
+                if let Some(pb) = self.bars.lock().unwrap().get(&file) {
+                    pb.set_position(downloaded);
+                }
+            }
+            Event::AssetRetry {
+                file,
+                mirror,
+                attempt,
+                max,
+            } => {
+                if let Some(pb) = self.bars.lock().unwrap().get(&file) {
+                    pb.reset();
+                    pb.set_position(0);
+                    pb.set_message(format!("使用镜像{mirror}重试中({attempt}/{max}) {file}"));
+                }
+            }
+            Event::AssetRequestFailed { file, reason, .. } => {
+                self.multi
+                    .println(format!("{RED}下载失败: {file} {reason}{RESET}"))
+                    .ok();
+            }
+            Event::AssetSlow { file } => {
+                if let Some(pb) = self.bars.lock().unwrap().get(&file) {
+                    pb.set_message("速度过慢，切换镜像...".to_string());
+                }
+            }
+            Event::AssetFinished { file, error } => {
+                if let Some(pb) = self.bars.lock().unwrap().remove(&file) {
+                    match error {
+                        Some(error) => pb.finish_with_message(error),
+                        None => pb.finish_with_message(format!("已完成: {file}")),
+                    }
+                }
+            }
+            Event::AssetTrashed { path } => {
+                println!("{YELLOW}多余文件已移至回收站: {path}{RESET}");
+            }
+        }
+    }
+
+    fn handle_json(&self, event: Event) {
+        match &event {
+            Event::AssetStarted { file, size } => {
+                self.progress
+                    .lock()
+                    .unwrap()
+                    .insert(file.clone(), (*size, 0));
+            }
+            Event::AssetProgress { file, downloaded } => {
+                let mut progress = self.progress.lock().unwrap();
+                let Some((size, last_percent)) = progress.get_mut(file) else {
+                    return;
+                };
+                let percent = (downloaded * 100).checked_div(*size).unwrap_or(100);
+                if percent == *last_percent && downloaded != size {
+                    return;
+                }
+                *last_percent = percent;
+            }
+            Event::AssetRetry { file, .. } => {
+                if let Some((_, last_percent)) = self.progress.lock().unwrap().get_mut(file) {
+                    *last_percent = 0;
+                }
+            }
+            Event::AssetFinished { file, .. } => {
+                self.progress.lock().unwrap().remove(file);
+            }
+            _ => {}
+        }
+        self.emit(event.to_json());
+    }
+
+    // 资源同步结束后清除进度条
+    pub fn clear(&self) {
+        self.multi.clear().ok();
+    }
+}
@@ -0,0 +1,147 @@
+// 更新过程中发出的事件。库本身不向标准输出打印任何内容，
+// 调用方通过 `Updater::on_event` 决定如何展示。
+
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    // 某个镜像获取远程版本失败，即将换下一个镜像重试
+    HeadRetry {
+        mirror: String,
+        reason: String,
+    },
+    // 开始下载某个代码文件
+    FileStarted {
+        file: String,
+    },
+    // 某个代码文件下载失败，即将换下一个镜像重试
+    FileRetry {
+        file: String,
+        mirror: String,
+        reason: String,
+    },
+    // 代码文件下载并校验完成，或所有镜像都失败
+    FileFinished {
+        file: String,
+        error: Option<String>,
+    },
+    // 需要下载或更新的美术资源数量
+    AssetsPlanned {
+        count: usize,
+    },
+    AssetStarted {
+        file: String,
+        size: u64,
+    },
+    AssetProgress {
+        file: String,
+        downloaded: u64,
+    },
+    // 第 attempt 次（从 1 开始）尝试下载某个资源
+    AssetRetry {
+        file: String,
+        mirror: String,
+        attempt: u64,
+        max: u64,
+    },
+    AssetRequestFailed {
+        file: String,
+        mirror: String,
+        reason: String,
+    },
+    // 下载速度过慢，主动中断当前镜像
+    AssetSlow {
+        file: String,
+    },
+    AssetFinished {
+        file: String,
+        error: Option<String>,
+    },
+    // 不在索引中的资源被移入回收站
+    AssetTrashed {
+        path: String,
+    },
+}
+
+impl Event {
+    // 机器可读的表示，`event` 字段为事件类型。字段名是对外约定，只增不改
+    pub fn to_json(&self) -> Value {
+        match self {
+            Event::HeadRetry { mirror, reason } => json!({
+                "event": "head_retry",
+                "mirror": mirror,
+                "reason": reason,
+            }),
+            Event::FileStarted { file } => json!({
+                "event": "file_started",
+                "file": file,
+            }),
+            Event::FileRetry {
+                file,
+                mirror,
+                reason,
+            } => json!({
+                "event": "file_retry",
+                "file": file,
+                "mirror": mirror,
+                "reason": reason,
+            }),
+            Event::FileFinished { file, error } => json!({
+                "event": "file_finished",
+                "file": file,
+                "ok": error.is_none(),
+                "error": error,
+            }),
+            Event::AssetsPlanned { count } => json!({
+                "event": "assets_planned",
+                "count": count,
+            }),
+            Event::AssetStarted { file, size } => json!({
+                "event": "asset_started",
+                "file": file,
+                "size": size,
+            }),
+            Event::AssetProgress { file, downloaded } => json!({
+                "event": "asset_progress",
+                "file": file,
+                "downloaded": downloaded,
+            }),
+            Event::AssetRetry {
+                file,
+                mirror,
+                attempt,
+                max,
+            } => json!({
+                "event": "asset_retry",
+                "file": file,
+                "mirror": mirror,
+                "attempt": attempt,
+                "max": max,
+            }),
+            Event::AssetRequestFailed {
+                file,
+                mirror,
+                reason,
+            } => json!({
+                "event": "asset_request_failed",
+                "file": file,
+                "mirror": mirror,
+                "reason": reason,
+            }),
+            Event::AssetSlow { file } => json!({
+                "event": "asset_slow",
+                "file": file,
+            }),
+            Event::AssetFinished { file, error } => json!({
+                "event": "asset_finished",
+                "file": file,
+                "ok": error.is_none(),
+                "error": error,
+            }),
+            Event::AssetTrashed { path } => json!({
+                "event": "asset_trashed",
+                "path": path,
+            }),
+        }
+    }
+}
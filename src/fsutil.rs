@@ -0,0 +1,142 @@
+// 文件写入工具。所有对游戏文件的写入都先写到同目录下的临时文件，
+// fsync 后再改名覆盖目标，断电或崩溃时目标文件要么是旧内容要么是新内容。
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// 写入中途的临时文件后缀，启动时残留的会被清理
+pub(crate) const TMP_SUFFIX: &str = ".krdove-tmp";
+
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = sibling_with_suffix(path, TMP_SUFFIX);
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result?;
+    sync_parent(path);
+    Ok(())
+}
+
+// 把已写完的同目录文件（如 .part）落盘后改名为目标文件
+pub(crate) fn persist(from: &Path, to: &Path) -> io::Result<()> {
+    fs::File::open(from)?.sync_all()?;
+    fs::rename(from, to)?;
+    sync_parent(to);
+    Ok(())
+}
+
+// 校验远程提供的相对路径（比较结果、文件树、资源索引中的文件名），返回规范化后的路径。
+// 拒绝空路径、绝对路径、盘符以及 `..`，防止写入或删除游戏目录以外的文件
+pub(crate) fn safe_relative_path(path: &str) -> Result<String, String> {
+    let unsafe_path = || format!("拒绝不安全的远程路径: {path:?}");
+    if path.starts_with('/') || path.starts_with('\\') || path.contains(':') || path.contains('\0')
+    {
+        return Err(unsafe_path());
+    }
+    let mut parts = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return Err(unsafe_path()),
+            part => parts.push(part),
+        }
+    }
+    if parts.is_empty() {
+        return Err(unsafe_path());
+    }
+    Ok(parts.join("/"))
+}
+
+pub(crate) fn sibling_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// 递归删除 dir 下残留的临时文件，返回被删除的文件
+pub(crate) fn clean_temporaries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                stack.push(path);
+            } else if file_type.is_file()
+                && path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.ends_with(TMP_SUFFIX))
+            {
+                fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+    }
+    Ok(removed)
+}
+
+// 目录项的改名也需要落盘；Windows 上无法打开目录，忽略即可
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_relative_path_normalizes_separators() {
+        assert_eq!(safe_relative_path("lua/a.lua").unwrap(), "lua/a.lua");
+        assert_eq!(
+            safe_relative_path("lua\\sub\\x.lua").unwrap(),
+            "lua/sub/x.lua"
+        );
+        assert_eq!(safe_relative_path("./lua//a.lua").unwrap(), "lua/a.lua");
+    }
+
+    #[test]
+    fn safe_relative_path_rejects_escapes() {
+        for path in [
+            "",
+            ".",
+            "..",
+            "../a.lua",
+            "lua/../../a.lua",
+            "lua\\..\\a.lua",
+            "/etc/passwd",
+            "\\a.lua",
+            "C:/a.lua",
+            "C:a.lua",
+            "a\0.lua",
+        ] {
+            assert!(safe_relative_path(path).is_err(), "{path:?}");
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// KingdomRushDove 更新引擎。
+//
+// 可执行文件只负责命令行交互与输出；其它启动器可以直接嵌入 `Updater`，
+// 通过 `Updater::on_event` 注册回调接收进度，而不是解析标准输出。
+
+mod assets;
+mod backup;
+mod config;
+mod event;
+mod fsutil;
+mod hash;
+mod mirrors;
+mod remote;
+mod scores;
+mod selfupdate;
+mod state;
+mod updater;
+mod version;
+
+pub use assets::{AssetEntry, AssetOutcome, AssetPlan, AssetReport, read_assets_index};
+pub use backup::BackupInfo;
+pub use config::{CONFIG_FILE, Config, MirrorConfig};
+pub use event::Event;
+pub use mirrors::ProbeResult;
+pub use remote::{
+    CommitInfo, Comparison, DiffAction, DiffRecord, Download, GitHubMirror, GiteeSource,
+    LocalSource, NotFound, RemoteSource, Unsupported, is_network_error, source_from_url,
+    sources_from_urls,
+};
+pub use scores::MirrorScore;
+pub use selfupdate::SelfUpdate;
+pub use state::Pin;
+pub use updater::{
+    ApplyReport, CheckResult, FileFailure, UpdatePlan, Updater, VerifyReport, WorkingMode,
+};
+pub use version::VERSION_FILE;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,591 +1,1222 @@
-use rayon::prelude::*;
-use reqwest::blocking::Client;
-use serde_json::Value;
-use std::fs;
-use std::io::{self, Read};
-const LOCAL_COMMIT_FILE: &str = "./current_version_commit_hash.txt";
-const ORIGINAL_COMMIT_FILE: &str = "./origin_version_commit_hash.txt";
-const GREEN: &str = "\x1b[32m";
-const YELLOW: &str = "\x1b[33m";
-const RED: &str = "\x1b[31m";
-const CYAN: &str = "\x1b[36m";
-const RESET: &str = "\x1b[0m";
-const WORK_DIR: &str = "Kingdom Rush";
+mod cli;
+mod console;
 
-const PROXY_LIST: [&str; 3] = [
-    "https://bgithub.xyz",
-    "https://dgithub.xyz",
-    "https://hub.gitmirror.com/https://github.com",
-];
-const MAX_RETRY: u64 = 3;
-const SPEED_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
-const MIN_SPEED: u64 = 10 * 1024; // 10KB/s
-
-use std::path::Path;
-
-fn diff_commit_gitee(
-    local_commit_hash: &str,
-    remote_commit_hash: &str,
-) -> Result<(Vec<String>, Vec<(DiffAction, String)>), Box<dyn std::error::Error>> {
-    let url = format!(
-        "https://gitee.com/api/v5/repos/CrazySpottedDove/KingdomRushDove/compare/{local_commit_hash}...{remote_commit_hash}"
-    );
-    let client = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()?;
-    let response_result = client.get(&url).header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36 Edg/141.0.0.0").send();
-    let Ok(response) = response_result else {
-        return Err(format!("请求 Gitee 比较接口失败: {:?}", response_result.err()).into());
-    };
-    let j_result = response.json::<serde_json::Value>();
-    let Ok(j) = j_result else {
-        return Err(format!("解析 Gitee 比较接口为 json 失败: {:?}", j_result.err()).into());
-    };
-    let commits = j["commits"].as_array().unwrap();
-    let mut messages = commits
-        .iter()
-        .map(|c| c["commit"]["message"].as_str().unwrap_or("").to_string())
-        .collect::<Vec<String>>();
-    messages.reverse();
-    let files = j["files"].as_array().unwrap();
-    let diff_records = files
-        .iter()
-        .map(|f| {
-            let filename = f["filename"].as_str().unwrap_or("").to_string();
-            let diff_action = match f["status"].as_str().unwrap_or("") {
-                "added" => DiffAction::Added,
-                "modified" => DiffAction::Modified,
-                "removed" => DiffAction::Removed,
-                _ => DiffAction::Modified,
-            };
-            (diff_action, filename)
-        })
-        .collect::<Vec<(DiffAction, String)>>();
-    Ok((messages, diff_records))
-}
+use cli::{Cli, Command, USAGE};
+use console::{CYAN, Console, GREEN, RED, RESET, YELLOW};
+use kingdom_rush_dove_updater::{
+    AssetPlan, AssetReport, CheckResult, Config, DiffAction, Error, Pin, Result, UpdatePlan,
+    Updater, VERSION_FILE, VerifyReport, WorkingMode, is_network_error, sources_from_urls,
+};
+use serde_json::{Value, json};
+use std::fmt;
+use std::sync::Arc;
+
+const WORK_DIR: &str = "Kingdom Rush";
+// 自我更新后重新启动时设置，避免再次检查
+const SELF_UPDATED_ENV: &str = "KRDOVE_SELF_UPDATED";
 
-#[derive(PartialEq)]
-enum WorkingMode {
-    Normal,
-    Fix,
+// 进程退出码，含义见 USAGE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    // 已更新，或命令正常完成
+    Success,
+    InternalError,
+    UpToDate,
+    // 仅检查时发现有可用的新版本
+    UpdateAvailable,
+    // 部分文件下载失败
+    PartialFailure,
+    NetworkUnreachable,
+    // 目录错误、缺少版本记录或安装不完整
+    InvalidInstall,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    if !cfg!(debug_assertions) {
-        if !is_current_dir_safe() {
-            println!(
-                "{RED}你在错误的目录运行了更新程序！请将更新程序放置在 {WORK_DIR} 目录后再运行。{RESET}"
-            );
-            wait_for_enter();
-            return Ok(());
+impl Outcome {
+    fn code(self) -> i32 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::InternalError => 1,
+            Outcome::UpToDate => 3,
+            Outcome::UpdateAvailable => 4,
+            Outcome::PartialFailure => 5,
+            Outcome::NetworkUnreachable => 6,
+            Outcome::InvalidInstall => 7,
         }
     }
 
-    // 让用户选择：正常更新或修复式更新。如果正常更新，输入 n 并回车；如果修复更新，输入 r 并回车
-    println!("{CYAN}请选择更新模式：{RESET}");
-    println!("{YELLOW}输入 n 并回车进行正常更新（默认）{RESET}");
-    println!("{YELLOW}输入 f 并回车进行修复式更新（重新下载所有代码文件）{RESET}");
-    let mut mode_input = String::new();
-    std::io::stdin().read_line(&mut mode_input).ok();
-    let mode_input = mode_input.trim().to_lowercase();
-    let working_mode = if mode_input == "f" {
-        WorkingMode::Fix
-    } else {
-        WorkingMode::Normal
-    };
+    // 按错误原因选择退出码
+    fn from_error(e: &Error) -> Self {
+        if e.downcast_ref::<InvalidInstall>().is_some() {
+            Outcome::InvalidInstall
+        } else if is_network_error(e) {
+            Outcome::NetworkUnreachable
+        } else {
+            Outcome::InternalError
+        }
+    }
+}
 
-    let local_commit_hash = match working_mode {
-        WorkingMode::Normal => read_local_commit_hash()?,
-        WorkingMode::Fix => read_original_commit_hash()?,
-    };
+// 游戏目录缺少版本记录等安装问题
+#[derive(Debug)]
+struct InvalidInstall(String);
 
-    println!("{CYAN}正在检查最新版本(ง •_•)ง{RESET}");
-    let remote_commit_hash = fetch_remote_commit_hash()?;
+impl fmt::Display for InvalidInstall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
-    if local_commit_hash == remote_commit_hash {
-        println!("{GREEN}已是最新，无需更新。{RESET}");
-        println!("{YELLOW}如果想强行检查美术资源，请输入 c 并回车{RESET}");
-        println!("{YELLOW}否则，按回车退出{RESET}");
+impl std::error::Error for InvalidInstall {}
 
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).ok();
-        if input.trim().eq_ignore_ascii_case("c") {
-            if let Err(e) = update_assets() {
-                eprintln!("{RED}资源检查/更新失败：{}{RESET}", e);
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli = match Cli::parse(args.iter().cloned()) {
+        Ok(cli) => cli,
+        Err(e) => {
+            // 参数解析失败时仍遵守 --json，不输出带颜色的文本
+            if args.iter().any(|arg| arg == "--json") {
+                println!(
+                    "{}",
+                    json!({
+                        "event": "error",
+                        "message": e.to_string(),
+                        "exit_code": 2,
+                    })
+                );
             } else {
-                println!("{GREEN}美术资源检查/更新完成！{RESET}");
+                eprintln!("{RED}{e}{RESET}");
+                eprintln!("{USAGE}");
             }
-            wait_for_enter();
+            std::process::exit(2);
         }
-        return Ok(());
+    };
+
+    if cli.command == Command::Help {
+        println!("{USAGE}");
+        return;
     }
 
-    println!("{GREEN}检测到新版本，进入更新例程(*^_^*){RESET}");
+    let console = Arc::new(Console::new(cli.json));
+    let outcome = run(&cli, &console).unwrap_or_else(|e| {
+        let outcome = Outcome::from_error(&e);
+        console.clear();
+        console.warn(RED, format!("错误：{e}"));
+        console.emit(json!({
+            "event": "error",
+            "message": e.to_string(),
+            "exit_code": outcome.code(),
+        }));
+        outcome
+    });
+    std::process::exit(outcome.code());
+}
 
-    println!("{CYAN}正在分析本地与远程文件差异，请稍候……{RESET}");
-    let (messages, diff_records) = diff_commit_gitee(&local_commit_hash, &remote_commit_hash)?;
+fn run(cli: &Cli, console: &Arc<Console>) -> Result<Outcome> {
+    if !cfg!(debug_assertions) && !is_current_dir_safe() {
+        console.say(
+            RED,
+            format!("你在错误的目录运行了更新程序！请将更新程序放置在 {WORK_DIR} 目录后再运行。"),
+        );
+        console.result(
+            cli.command.name(),
+            "wrong_directory",
+            json!({ "expected_dir": WORK_DIR }),
+        );
+        pause(cli);
+        return Ok(Outcome::InvalidInstall);
+    }
 
-    // if working_mode == WorkingMode::Normal {
-    for (diff_action, diff_file) in &diff_records {
-        match *diff_action {
-            DiffAction::Added => println!("{GREEN}  + {diff_file}{RESET}"),
-            DiffAction::Modified => println!("{YELLOW}  ~ {diff_file}{RESET}"),
-            DiffAction::Removed => println!("{RED}  - {diff_file}{RESET}"),
+    let config = Config::load(".")?;
+    let mut updater = {
+        let console = Arc::clone(console);
+        Updater::new(".")
+            .with_config(&config)?
+            .read_only(cli.dry_run)
+            .on_event(move |e| console.handle(e))
+    };
+    // 预演时不做任何写入，残留的临时文件留给下次正式运行清理
+    if !cli.dry_run {
+        match updater.clean_temporaries() {
+            Ok(removed) if !removed.is_empty() => {
+                console.say(
+                    YELLOW,
+                    format!("已清理上次中断时残留的 {} 个临时文件", removed.len()),
+                );
+            }
+            Ok(_) => {}
+            Err(e) => console.warn(YELLOW, format!("清理临时文件失败：{e}")),
         }
     }
-    // }
-    println!("{CYAN}正在下载新文件ε=( o｀ω′)ノ请等待哟(＾Ｕ＾)ノ~ＹＯ{RESET}");
-
-    // 下载差分文件并更新本地文件
-    let results: Vec<_> = diff_records
-        .par_iter()
-        .map(|file| download_and_replace_file(file).map_err(|e| format!("{}: {}", file.1, e)))
-        .collect();
-
-    let errors: Vec<_> = results.into_iter().filter_map(|res| res.err()).collect();
+    if !cli.sources.is_empty() {
+        updater = updater.with_sources(sources_from_urls(cli.sources.iter().map(String::as_str))?);
+    }
+    // 只在会更新游戏的命令中自我更新，只读与查询类命令不下载、不替换任何文件
+    let updates = matches!(
+        cli.command,
+        Command::Interactive | Command::Update | Command::Fix | Command::Repair | Command::Assets
+    );
+    if updates
+        && !cli.no_self_update
+        && !cli.dry_run
+        && std::env::var_os(SELF_UPDATED_ENV).is_none()
+    {
+        self_update(&updater, console);
+    }
 
-    if !errors.is_empty() {
-        println!("{RED}部分文件下载失败，未更新本地版本记录：{RESET}");
-        for err in errors {
-            println!("{RED}  - {}{RESET}", err);
+    match cli.command {
+        Command::Interactive => {
+            // 让用户选择：正常更新或修复式更新。如果正常更新，输入 n 并回车；如果修复更新，输入 f 并回车
+            println!("{CYAN}请选择更新模式：{RESET}");
+            println!("{YELLOW}输入 n 并回车进行正常更新（默认）{RESET}");
+            println!("{YELLOW}输入 f 并回车进行修复式更新（重新下载所有代码文件）{RESET}");
+            println!("{YELLOW}输入 r 并回车校验本地文件，只重新下载缺失或损坏的文件{RESET}");
+            let mode_input = read_input();
+            let working_mode = if mode_input.eq_ignore_ascii_case("f") {
+                WorkingMode::Fix
+            } else if mode_input.eq_ignore_ascii_case("r") {
+                WorkingMode::Repair
+            } else {
+                WorkingMode::Normal
+            };
+            rank_mirrors(&mut updater, console);
+            run_update(cli, &mut updater, console, working_mode)
         }
-        println!("{CYAN}请修复网络或稍后重试。{RESET}");
-
-        wait_for_enter();
-        return Ok(());
+        Command::Update => {
+            rank_mirrors(&mut updater, console);
+            run_update(cli, &mut updater, console, WorkingMode::Normal)
+        }
+        Command::Fix => {
+            rank_mirrors(&mut updater, console);
+            run_update(cli, &mut updater, console, WorkingMode::Fix)
+        }
+        Command::Repair => {
+            rank_mirrors(&mut updater, console);
+            run_update(cli, &mut updater, console, WorkingMode::Repair)
+        }
+        Command::Assets if cli.dry_run => {
+            let local_commit = installed_commit(&updater)?;
+            let plan =
+                updater.plan_between(WorkingMode::Normal, local_commit.clone(), local_commit)?;
+            let assets = updater.preview(&plan)?;
+            print_asset_plan(console, &assets);
+            console.result(
+                cli.command.name(),
+                "dry_run",
+                json!({ "assets": asset_plan_json(&assets) }),
+            );
+            pause(cli);
+            Ok(if assets.count() == 0 && assets.trash.is_empty() {
+                Outcome::UpToDate
+            } else {
+                Outcome::UpdateAvailable
+            })
+        }
+        Command::Assets => {
+            rank_mirrors(&mut updater, console);
+            let result = run_assets(cli, &updater, console);
+            pause(cli);
+            result
+        }
+        Command::Status => {
+            console.phase("check");
+            let check = CheckResult {
+                local_commit: installed_commit(&updater)?,
+                remote_commit: updater.remote_commit()?,
+            };
+            console.say(CYAN, format!("更新通道: {}", updater.channel()));
+            let local_version = updater.version_id();
+            // 版本号只用于显示，获取失败不影响检查结果
+            let remote_version = updater
+                .remote_version_id(&check.remote_commit, None)
+                .ok()
+                .flatten();
+            let show = |v: &Option<String>| v.as_deref().unwrap_or("未知").to_string();
+            console.say(
+                CYAN,
+                format!(
+                    "本地版本: {}（{}）",
+                    show(&local_version),
+                    check.local_commit
+                ),
+            );
+            console.say(
+                CYAN,
+                format!(
+                    "远程版本: {}（{}）",
+                    show(&remote_version),
+                    check.remote_commit
+                ),
+            );
+            if let Some(pin) = updater.pin() {
+                console.say(
+                    YELLOW,
+                    format!("已固定在版本: {}（{}）", pin.rev, pin.commit),
+                );
+            }
+            if check.is_up_to_date() {
+                console.say(GREEN, "已是最新。");
+            } else {
+                console.say(YELLOW, "有可用的新版本。");
+            }
+            console.result(
+                cli.command.name(),
+                if check.is_up_to_date() {
+                    "up_to_date"
+                } else {
+                    "update_available"
+                },
+                json!({
+                    "channel": updater.channel(),
+                    "pin": updater.pin().map(|pin| json!({ "rev": pin.rev, "commit": pin.commit })),
+                    "local_commit": check.local_commit,
+                    "remote_commit": check.remote_commit,
+                    "local_version": local_version,
+                    "remote_version": remote_version,
+                }),
+            );
+            pause(cli);
+            Ok(check_outcome(&check))
+        }
+        Command::Check => {
+            console.phase("check");
+            let check = CheckResult {
+                local_commit: installed_commit(&updater)?,
+                remote_commit: updater.remote_commit()?,
+            };
+            if check.is_up_to_date() {
+                console.say(GREEN, "已是最新，无需更新。");
+            } else {
+                console.say(
+                    YELLOW,
+                    format!(
+                        "检测到新版本: {} -> {}",
+                        check.local_commit, check.remote_commit
+                    ),
+                );
+            }
+            console.result(
+                cli.command.name(),
+                if check.is_up_to_date() {
+                    "up_to_date"
+                } else {
+                    "update_available"
+                },
+                json!({
+                    "local_commit": check.local_commit,
+                    "remote_commit": check.remote_commit,
+                }),
+            );
+            pause(cli);
+            Ok(check_outcome(&check))
+        }
+        Command::Verify => {
+            console.phase("verify");
+            console.say(CYAN, "正在校验本地文件，请稍候……");
+            let report = updater.verify()?;
+            print_verify_report(console, &report);
+            console.result(
+                cli.command.name(),
+                if report.is_clean() {
+                    "clean"
+                } else {
+                    "problems"
+                },
+                json!({
+                    "commit": report.commit,
+                    "missing": report.missing,
+                    "modified": report.modified,
+                    "extra": report.extra,
+                    "size_mismatched": report.size_mismatched,
+                }),
+            );
+            pause(cli);
+            Ok(if report.is_clean() {
+                Outcome::Success
+            } else {
+                Outcome::InvalidInstall
+            })
+        }
+        Command::Rollback => {
+            let result = run_rollback(cli, &updater, console);
+            pause(cli);
+            result
+        }
+        Command::History => run_history(cli, &mut updater, console),
+        Command::Channel => {
+            let result = run_channel(cli, &mut updater, console);
+            pause(cli);
+            result
+        }
+        Command::MirrorsBench => {
+            console.phase("probe_mirrors");
+            console.say(CYAN, "正在测试镜像，请稍候……");
+            let results = updater.rank_mirrors();
+            console.say(CYAN, "排名  延迟       成功率  平均速度      镜像");
+            let mut mirrors = Vec::new();
+            for (i, result) in results.iter().enumerate() {
+                let score = updater.mirror_score(&result.mirror);
+                mirrors.push(json!({
+                    "mirror": result.mirror,
+                    "latency_ms": result.latency.map(|l| l.as_millis() as u64),
+                    "error": result.error,
+                    "success_rate": score.success_rate(),
+                    "throughput": score.throughput(),
+                }));
+                let success_rate = score
+                    .success_rate()
+                    .map(|r| format!("{:.0}%", r * 100.0))
+                    .unwrap_or_else(|| "-".to_string());
+                let throughput = score
+                    .throughput()
+                    .map(|t| format!("{:.1} KB/s", t / 1024.0))
+                    .unwrap_or_else(|| "-".to_string());
+                let latency = result
+                    .latency
+                    .map(|l| format!("{} ms", l.as_millis()))
+                    .unwrap_or_else(|| "不可用".to_string());
+                let color = if result.is_healthy() { GREEN } else { RED };
+                console.say(
+                    color,
+                    format!(
+                        "{:<4}  {:<9}  {:<6}  {:<12}  {}  {}",
+                        i + 1,
+                        latency,
+                        success_rate,
+                        throughput,
+                        result.mirror,
+                        result.error.as_deref().unwrap_or("")
+                    ),
+                );
+            }
+            console.result(cli.command.name(), "ok", json!({ "mirrors": mirrors }));
+            pause(cli);
+            Ok(if results.iter().any(|r| r.is_healthy()) {
+                Outcome::Success
+            } else {
+                Outcome::NetworkUnreachable
+            })
+        }
+        Command::Help => unreachable!(),
     }
+}
 
-    println!("{GREEN}代码文件更新完成(＾Ｕ＾)ノ~ＹＯ{RESET}");
+// 列出当前通道最近的提交，并让用户选择一个版本更新过去
+fn run_history(cli: &Cli, updater: &mut Updater, console: &Console) -> Result<Outcome> {
+    console.phase("check");
+    let local_commit = installed_commit(updater)?;
+    let head = updater.remote_commit()?;
+    let commits = updater.history(cli.history_limit)?;
+    let pinned = updater.pin().map(|pin| pin.commit.clone());
+    // 只显示本地已知的游戏版本号，不为每个提交下载 version.lua
+    let versions = updater.known_versions();
 
-    if working_mode == WorkingMode::Normal {
-        println!("{CYAN}本次更新内容摘要：{RESET}");
-        for message in &messages {
-            print!("{YELLOW} - {message}{RESET}");
+    console.say(
+        CYAN,
+        format!(
+            "更新通道 {} 最近的 {} 个提交：",
+            updater.channel(),
+            commits.len()
+        ),
+    );
+    for (i, commit) in commits.iter().enumerate() {
+        let installed = commit.sha == local_commit;
+        let mut marks = Vec::new();
+        if commit.sha == head {
+            marks.push("最新");
+        }
+        if installed {
+            marks.push("已安装");
+        }
+        if pinned.as_ref() == Some(&commit.sha) {
+            marks.push("已固定");
         }
-        io::stdout().flush().ok();
+        let marks = if marks.is_empty() {
+            String::new()
+        } else {
+            format!("  [{}]", marks.join("，"))
+        };
+        let version = versions
+            .get(&commit.sha)
+            .map(|id| format!("  (版本 {id})"))
+            .unwrap_or_default();
+        let date = commit.date.replacen('T', " ", 1);
+        console.say(
+            if installed { GREEN } else { "" },
+            format!(
+                "{:>3}. {}{version}  {}  {}  {}{marks}",
+                i + 1,
+                commit.short_sha(),
+                date.get(..16).unwrap_or(&date),
+                commit.author,
+                commit.summary()
+            ),
+        );
     }
+    if !commits.iter().any(|c| c.sha == local_commit) {
+        console.say(YELLOW, format!("本地版本 {local_commit} 不在以上列表中。"));
+    }
+    console.result(
+        cli.command.name(),
+        "ok",
+        json!({
+            "channel": updater.channel(),
+            "local_commit": local_commit,
+            "remote_commit": head,
+            "commits": commits
+                .iter()
+                .map(|c| json!({
+                    "sha": c.sha,
+                    "author": c.author,
+                    "date": c.date,
+                    "summary": c.summary(),
+                    "version": versions.get(&c.sha),
+                    "installed": c.sha == local_commit,
+                    "head": c.sha == head,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+    );
 
-    update_assets()?;
+    if cli.yes || commits.is_empty() {
+        pause(cli);
+        return Ok(Outcome::Success);
+    }
+    println!("{YELLOW}输入序号并回车更新到该版本，直接回车退出{RESET}");
+    let input = read_input();
+    if input.is_empty() {
+        return Ok(Outcome::Success);
+    }
+    let Some(commit) = input
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| commits.get(i))
+    else {
+        console.say(RED, format!("无效的序号: {input}"));
+        pause(cli);
+        return Ok(Outcome::Success);
+    };
+    // 选择最新版本等同于离开固定版本的正常更新，选择其它版本则固定在该版本
+    let mut cli = cli.clone();
+    cli.command = Command::Update;
+    cli.target = (commit.sha != head).then(|| commit.sha.clone());
+    cli.unpin = cli.target.is_none();
+    rank_mirrors(updater, console);
+    run_update(&cli, updater, console, WorkingMode::Normal)
+}
 
-    println!("{GREEN}所有资源全部更新完成o(*￣▽￣*)ブ{RESET}");
+// 显示或切换更新通道。切换只记录通道，不立即更新
+fn run_channel(cli: &Cli, updater: &mut Updater, console: &Console) -> Result<Outcome> {
+    let previous = updater.channel().to_string();
+    let Some(channel) = &cli.channel else {
+        console.say(CYAN, format!("当前更新通道: {previous}"));
+        console.result(cli.command.name(), "ok", json!({ "channel": previous }));
+        return Ok(Outcome::Success);
+    };
+    if cli.dry_run {
+        console.say(
+            CYAN,
+            format!("[预演] 将把更新通道从 {previous} 切换到 {channel}"),
+        );
+        console.result(
+            cli.command.name(),
+            "dry_run",
+            json!({ "previous_channel": previous, "channel": channel }),
+        );
+        return Ok(Outcome::Success);
+    }
+    console.phase("check");
+    let head = updater.set_channel(channel)?;
+    console.say(
+        GREEN,
+        format!("已将更新通道从 {previous} 切换到 {channel}，最新版本: {head}"),
+    );
+    console.say(CYAN, "运行 update 即可更新到该通道的最新版本。");
+    console.result(
+        cli.command.name(),
+        "switched",
+        json!({ "previous_channel": previous, "channel": channel, "remote_commit": head }),
+    );
+    Ok(Outcome::Success)
+}
 
-    // 写回最新 commit_hash
-    fs::write(LOCAL_COMMIT_FILE, &remote_commit_hash)?;
-    println!("{GREEN}已更新本地版本记录(●'◡'●)。{RESET}");
-    wait_for_enter();
+// 本次更新的目标版本：--to 指定的版本、固定的版本或当前通道的最新版本。
+// 正常更新会离开固定版本时先询问，不离开则以固定版本为目标
+fn target_commit(
+    cli: &Cli,
+    updater: &Updater,
+    console: &Console,
+    working_mode: WorkingMode,
+) -> Result<String> {
+    if let Some(rev) = &cli.target {
+        let commit = updater.resolve(rev)?;
+        console.say(CYAN, format!("目标版本: {rev}（{commit}）"));
+        return Ok(commit);
+    }
+    let remote_commit = updater.remote_commit()?;
+    let Some(pin) = updater.pin() else {
+        return Ok(remote_commit);
+    };
+    // 修复式更新不应离开固定版本，始终以固定版本为准
+    if working_mode != WorkingMode::Normal || pin.commit == remote_commit {
+        return Ok(pin.commit.clone());
+    }
+    console.say(
+        YELLOW,
+        format!(
+            "当前固定在版本 {}（{}），最新版本为 {remote_commit}",
+            pin.rev, pin.commit
+        ),
+    );
+    let leave = if cli.unpin {
+        true
+    } else if cli.yes {
+        false
+    } else {
+        println!("{YELLOW}输入 y 并回车离开固定版本，更新到最新版本；按回车保持固定版本{RESET}");
+        read_input().eq_ignore_ascii_case("y")
+    };
+    if leave {
+        Ok(remote_commit)
+    } else {
+        console.say(CYAN, "保持固定版本。需要离开时请使用 update --unpin。");
+        Ok(pin.commit.clone())
+    }
+}
+
+// 更新到目标版本后记录固定版本；正常更新离开固定版本后解除固定
+fn record_pin(cli: &Cli, updater: &mut Updater, console: &Console, to_commit: &str) -> Result<()> {
+    if cli.dry_run {
+        return Ok(());
+    }
+    if let Some(rev) = &cli.target {
+        updater.set_pin(Some(Pin {
+            rev: rev.clone(),
+            commit: to_commit.to_string(),
+        }))?;
+        console.say(
+            CYAN,
+            format!("已固定在版本 {rev}，之后的正常更新会先询问是否离开。"),
+        );
+    } else if updater.pin().is_some_and(|pin| pin.commit != to_commit) {
+        updater.set_pin(None)?;
+    }
     Ok(())
 }
 
-fn is_current_dir_safe() -> bool {
-    let current_dir = match std::env::current_dir() {
-        Ok(dir) => dir,
-        Err(_) => return false,
+fn run_update(
+    cli: &Cli,
+    updater: &mut Updater,
+    console: &Console,
+    working_mode: WorkingMode,
+) -> Result<Outcome> {
+    let command = cli.command.name();
+    console.phase("check");
+    console.say(CYAN, "正在检查最新版本(ง •_•)ง");
+    let from_commit = match working_mode {
+        WorkingMode::Normal | WorkingMode::Repair => installed_commit(updater)?,
+        WorkingMode::Fix => updater
+            .original_commit()
+            .map_err(|e| InvalidInstall(format!("无法读取原始版本记录：{e}")))?,
     };
-    if let Some(dir_name) = current_dir.file_name() {
-        if dir_name == WORK_DIR {
-            return true;
+    // 修复只校验当前安装的版本，与 verify 一致，不会顺带改变版本
+    let remote_commit = if working_mode == WorkingMode::Repair {
+        from_commit.clone()
+    } else {
+        target_commit(cli, updater, console, working_mode)?
+    };
+
+    if working_mode == WorkingMode::Repair {
+        console.say(CYAN, "正在校验本地文件，请稍候……");
+    } else if from_commit == remote_commit && cli.dry_run {
+        console.say(GREEN, "已是最新，无需更新。");
+    } else if from_commit == remote_commit {
+        console.say(GREEN, "已是最新，无需更新。");
+        record_pin(cli, updater, console, &remote_commit)?;
+        let check_assets = if cli.yes {
+            true
+        } else {
+            println!("{YELLOW}如果想强行检查美术资源，请输入 c 并回车{RESET}");
+            println!("{YELLOW}否则，按回车退出{RESET}");
+            read_input().eq_ignore_ascii_case("c")
+        };
+        if check_assets {
+            // 代码已是最新，资源也补齐时仍按"已是最新"退出，只有资源下载失败才改变退出码
+            let result = run_assets(cli, updater, console).map(|outcome| match outcome {
+                Outcome::Success => Outcome::UpToDate,
+                outcome => outcome,
+            });
+            pause(cli);
+            return result;
         }
+        console.result(
+            command,
+            "up_to_date",
+            json!({ "local_commit": from_commit, "remote_commit": remote_commit }),
+        );
+        return Ok(Outcome::UpToDate);
+    } else {
+        console.say(GREEN, "检测到新版本，进入更新例程(*^_^*)");
+        console.say(CYAN, "正在分析本地与远程文件差异，请稍候……");
     }
-    false
-}
+    console.phase("plan");
+    let plan = updater.plan_between(working_mode, from_commit, remote_commit)?;
+    console.emit(plan_json(&plan));
+    let current_version = updater.version_id();
 
-fn wait_for_enter() {
-    println!("{CYAN}按回车键退出...{RESET}");
-    let mut _wait = String::new();
-    std::io::stdin().read_line(&mut _wait).ok();
-}
+    if !plan.untracked.is_empty() {
+        console.say(YELLOW, "以下本地文件不在远程版本中（未做改动）：");
+        for file in &plan.untracked {
+            console.say(YELLOW, format!("  ? {file}"));
+        }
+    }
+    if working_mode == WorkingMode::Repair && plan.files.is_empty() {
+        console.say(GREEN, "所有代码文件均完好。");
+    }
 
-fn read_local_commit_hash() -> io::Result<String> {
-    let mut file = fs::File::open(LOCAL_COMMIT_FILE)?;
-    let mut hash = String::new();
-    file.read_to_string(&mut hash)?;
-    Ok(hash.trim().to_string())
-}
+    for (diff_action, diff_file) in &plan.files {
+        match diff_action {
+            DiffAction::Added => console.say(GREEN, format!("  + {diff_file}")),
+            DiffAction::Modified => console.say(YELLOW, format!("  ~ {diff_file}")),
+            DiffAction::Removed => console.say(RED, format!("  - {diff_file}")),
+            DiffAction::Renamed { from } => console.say(CYAN, format!("  > {from} -> {diff_file}")),
+        }
+    }
+    if cli.dry_run {
+        // version.lua 不在变动中时版本号不变，省去一次下载
+        let new_version = if plan.files.iter().any(|(_, f)| f == VERSION_FILE) {
+            let blob = plan.blobs.get(VERSION_FILE).map(String::as_str);
+            updater
+                .remote_version_id(&plan.to_commit, blob)
+                .ok()
+                .flatten()
+        } else {
+            current_version.clone()
+        };
+        console.say(
+            CYAN,
+            format!(
+                "[预演] 游戏版本: {}",
+                version_change(&current_version, &new_version)
+            ),
+        );
+        let assets = print_dry_run(updater, console, &plan)?;
+        console.result(
+            command,
+            "dry_run",
+            json!({
+                "from_commit": plan.from_commit,
+                "to_commit": plan.to_commit,
+                "from_version": current_version,
+                "to_version": new_version,
+                "assets": asset_plan_json(&assets),
+            }),
+        );
+        pause(cli);
+        return Ok(
+            if plan.files.is_empty() && assets.count() == 0 && assets.trash.is_empty() {
+                Outcome::UpToDate
+            } else {
+                Outcome::UpdateAvailable
+            },
+        );
+    }
+    console.phase("download");
+    console.say(CYAN, "正在下载新文件ε=( o｀ω′)ノ请等待哟(＾Ｕ＾)ノ~ＹＯ");
 
-fn read_original_commit_hash() -> io::Result<String> {
-    let mut file = fs::File::open(ORIGINAL_COMMIT_FILE)?;
-    let mut hash = String::new();
-    file.read_to_string(&mut hash)?;
-    Ok(hash.trim().to_string())
-}
+    let report = updater.apply(&plan)?;
+    console.clear();
 
-fn fetch_remote_commit_hash() -> Result<String, Box<dyn std::error::Error>> {
-    for retry in 0..MAX_RETRY {
-        let proxy = PROXY_LIST[(retry as usize) % PROXY_LIST.len()];
-        let url = format!(
-            "{}/CrazySpottedDove/KingdomRushDove/commits/deferred_commit_data/master?original_branch=master",
-            proxy
+    if !report.failed_files.is_empty() {
+        console.say(RED, "部分文件下载失败，未更新本地版本记录：");
+        for failure in &report.failed_files {
+            console.say(RED, format!("  - {}: {}", failure.file, failure.error));
+        }
+        console.say(CYAN, "请修复网络或稍后重试。");
+        console.result(
+            command,
+            "failed",
+            json!({
+                "from_commit": plan.from_commit,
+                "to_commit": plan.to_commit,
+                "failed_files": report
+                    .failed_files
+                    .iter()
+                    .map(|f| json!({ "file": f.file, "error": f.error }))
+                    .collect::<Vec<_>>(),
+            }),
         );
-        let client = reqwest::blocking::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()?;
-        let response = client
-        .get(url)
-        .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36 Edg/141.0.0.0")
-        .header("Accept", "application/json")
-        .header("Content-Type", "application/json")
-        .header("X-Requested-With", "XMLHttpRequest")
-        .send()?;
-
-        if !response.status().is_success() {
-            println!(
-                "{RED}尝试使用镜像{}获取远程版本失败，状态码: {}，正在重试...{RESET}",
-                proxy,
-                response.status()
-            );
-            continue;
+
+        pause(cli);
+        return Ok(Outcome::PartialFailure);
+    }
+
+    console.say(GREEN, "代码文件更新完成(＾Ｕ＾)ノ~ＹＯ");
+
+    if working_mode == WorkingMode::Normal {
+        console.say(CYAN, "本次更新内容摘要：");
+        for message in &plan.messages {
+            console.say(YELLOW, format!(" - {}", message.trim_end()));
         }
-        let response_text = response.text()?;
-        // 打印响应内容以调试
-        // println!("Remote commit API response: {}", response_text);
+    }
 
-        let json: Value = serde_json::from_str(&response_text)?;
-        let deferred_commits = json["deferredCommits"]
-            .as_array()
-            .ok_or("Failed to parse 'deferredCommits' array")?;
+    if let Some(assets) = &report.assets
+        && !assets.is_complete()
+    {
+        print_failed_assets(console, assets);
+        console.result(
+            command,
+            "failed",
+            json!({
+                "from_commit": plan.from_commit,
+                "to_commit": plan.to_commit,
+                "failed_assets": failed_assets_json(assets),
+            }),
+        );
+        pause(cli);
+        return Ok(Outcome::PartialFailure);
+    }
 
-        // 获取第一个 commit 的 oid
-        let remote_commit_hash = deferred_commits
-            .get(0)
-            .and_then(|commit| commit["oid"].as_str())
-            .ok_or("Failed to parse remote commit hash")?;
-        return Ok(remote_commit_hash.to_string());
+    console.say(GREEN, "所有资源全部更新完成o(*￣▽￣*)ブ");
+    console.say(GREEN, "已更新本地版本记录(●'◡'●)。");
+    let new_version = updater.version_id();
+    console.say(
+        GREEN,
+        format!(
+            "游戏版本: {}",
+            version_change(&current_version, &new_version)
+        ),
+    );
+    if working_mode != WorkingMode::Repair {
+        record_pin(cli, updater, console, &plan.to_commit)?;
     }
-    Err("Failed to fetch remote commit hash after retries".into())
+    console.result(
+        command,
+        "updated",
+        json!({
+            "from_commit": plan.from_commit,
+            "to_commit": plan.to_commit,
+            "from_version": current_version,
+            "to_version": new_version,
+            "files": plan.files.len(),
+            "assets": report.assets.as_ref().map_or(0, |a| a.files.len()),
+        }),
+    );
+    pause(cli);
+    Ok(Outcome::Success)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DiffAction {
-    Added,
-    Modified,
-    Removed,
+// 形如 "1.4.1 → 1.4.2"，读不到的版本号显示为未知
+fn version_change(from: &Option<String>, to: &Option<String>) -> String {
+    let show = |v: &Option<String>| v.clone().unwrap_or_else(|| "未知".to_string());
+    format!("{} → {}", show(from), show(to))
 }
 
-fn download_and_replace_file(
-    file: &(DiffAction, String),
-) -> Result<(), Box<dyn std::error::Error>> {
-    let (diff_action, file) = file;
-    if *diff_action == DiffAction::Removed {
-        let path = Path::new(file);
-        if path.exists() {
-            fs::remove_file(path)?;
-        }
-        return Ok(());
+fn print_dry_run(updater: &Updater, console: &Console, plan: &UpdatePlan) -> Result<AssetPlan> {
+    console.say(
+        CYAN,
+        format!(
+            "[预演] 提交范围: {} -> {}",
+            plan.from_commit, plan.to_commit
+        ),
+    );
+    for message in &plan.messages {
+        console.say(YELLOW, format!(" - {}", message.trim_end()));
     }
+    let count = |f: fn(&DiffAction) -> bool| plan.files.iter().filter(|(a, _)| f(a)).count();
+    console.say(
+        CYAN,
+        format!(
+            "[预演] 代码文件: 新增 {}，修改 {}，删除 {}，改名 {}",
+            count(|a| *a == DiffAction::Added),
+            count(|a| *a == DiffAction::Modified),
+            count(|a| *a == DiffAction::Removed),
+            count(|a| matches!(a, DiffAction::Renamed { .. }))
+        ),
+    );
+    let assets = updater.preview(plan)?;
+    print_asset_plan(console, &assets);
+    console.say(GREEN, "[预演] 未做任何修改。");
+    Ok(assets)
+}
 
-    let mut last_err = None;
-    for retry in 0..MAX_RETRY {
-        let proxy = PROXY_LIST[(retry as usize) % PROXY_LIST.len()];
-        let url = format!(
-            "{}/CrazySpottedDove/KingdomRushDove/raw/master/{}",
-            proxy, file
+fn print_asset_plan(console: &Console, assets: &AssetPlan) {
+    console.say(
+        CYAN,
+        format!(
+            "[预演] 需要下载的资源: {} 个，共 {}",
+            assets.count(),
+            format_bytes(assets.total_bytes())
+        ),
+    );
+    for (release, files) in &assets.downloads {
+        let bytes = files.iter().map(|(_, size)| size).sum();
+        console.say(
+            YELLOW,
+            format!(
+                "  release {release}: {} 个，{}",
+                files.len(),
+                format_bytes(bytes)
+            ),
         );
-        let client = reqwest::blocking::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build();
-        let client = match client {
-            Ok(c) => c,
-            Err(e) => {
-                last_err = Some(format!("构建HTTP客户端失败: {:?}", e));
-                continue;
-            }
-        };
-        let resp = client.get(&url).header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36 Edg/141.0.0.0").send();
-        match resp {
-            Ok(response) if response.status().is_success() => {
-                let content = response.bytes()?;
-                let path = Path::new(file);
-                if let Some(parent) = path.parent() {
-                    fs::create_dir_all(parent)?;
-                }
-                fs::write(path, &content)?;
-                // println!("{GREEN}已更新: {}{RESET}", file);
-                return Ok(());
-            }
-            Ok(response) => {
-                eprintln!(
-                    "{YELLOW}下载失败: {url} 状态码: {}{RESET}，已为您重试",
-                    response.status()
-                );
-                last_err = Some(format!(
-                    "Failed to download file: {}. HTTP Status: {}",
-                    file,
-                    response.status()
-                ));
-            }
-            Err(e) => {
-                eprintln!("{YELLOW}请求失败: {url} 错误: {e:?}{RESET}，已为您重试");
-                last_err = Some(format!("请求失败: {} 错误: {:?}", url, e));
-            }
+        for (file, size) in files {
+            console.say("", format!("      {file}（{}）", format_bytes(*size)));
         }
     }
+    if !assets.trash.is_empty() {
+        console.say(CYAN, "[预演] 将移入回收站的资源:");
+        for file in &assets.trash {
+            console.say(RED, format!("  - {file}"));
+        }
+    }
+}
 
-    Err(last_err
-        .unwrap_or_else(|| "未知下载错误".to_string())
-        .into())
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
 }
 
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::collections::HashMap;
-use std::io::Write;
-use std::sync::{Arc, Mutex};
-
-fn update_assets() -> Result<(), Box<dyn std::error::Error>> {
-    let assets_index = read_assets_index("_assets/assets_index.lua")?;
-    let assets_dir = "_assets";
-    let trashed_dir = "_trashed_assets";
-
-    let mut download_batches: HashMap<String, Vec<(String, u64)>> = HashMap::new();
-    let mut assets_count = 0;
-    for (path, info) in &assets_index {
-        let fullpath = format!("{}/{}", assets_dir, path);
-        let local_size = file_size(&fullpath);
-        let filename = Path::new(&path)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or(&path);
-        if local_size != *info {
-            let release = get_release_for_file(filename);
-            download_batches
-                .entry(release)
-                .or_insert_with(Vec::new)
-                .push((path.clone(), *info));
-            assets_count += 1;
-        }
-    }
-
-    println!(
-        "{CYAN}需要下载或更新的美术资源数量: {} 个{RESET}",
-        assets_count
+fn run_rollback(cli: &Cli, updater: &Updater, console: &Console) -> Result<Outcome> {
+    let command = cli.command.name();
+    let local_commit = installed_commit(updater)?;
+    let Some(backup) = updater.rollback_backup(&local_commit) else {
+        console.say(
+            YELLOW,
+            format!("没有可回滚的更新：当前版本 {local_commit} 没有对应的备份。"),
+        );
+        console.result(
+            command,
+            "nothing_to_rollback",
+            json!({ "local_commit": local_commit }),
+        );
+        return Ok(Outcome::Success);
+    };
+    console.say(
+        CYAN,
+        format!(
+            "将从 {} 回滚到 {}（游戏版本 {}；还原 {} 个文件，删除 {} 个新增文件）",
+            backup.to_commit,
+            backup.from_commit,
+            version_change(&backup.to_version, &backup.from_version),
+            backup.saved.len() + backup.trashed.len(),
+            backup.added.len()
+        ),
     );
+    if cli.dry_run {
+        for file in backup.saved.iter().chain(&backup.trashed) {
+            console.say("", format!("  [预演] 还原 {file}"));
+        }
+        for file in &backup.added {
+            console.say("", format!("  [预演] 删除 {file}"));
+        }
+        console.say(GREEN, "[预演] 未做任何修改。");
+        console.result(
+            command,
+            "dry_run",
+            json!({
+                "from_commit": backup.to_commit,
+                "to_commit": backup.from_commit,
+                "from_version": backup.to_version,
+                "to_version": backup.from_version,
+                "restored": backup.saved.iter().chain(&backup.trashed).collect::<Vec<_>>(),
+                "removed": backup.added,
+            }),
+        );
+        return Ok(Outcome::Success);
+    }
+    if !cli.yes {
+        println!("{YELLOW}确认回滚请输入 y 并回车{RESET}");
+        if !read_input().eq_ignore_ascii_case("y") {
+            println!("{CYAN}已取消回滚。{RESET}");
+            return Ok(Outcome::Success);
+        }
+    }
+    console.phase("rollback");
+    let backup = updater.rollback()?;
+    console.say(GREEN, format!("已回滚到 {}(●'◡'●)", backup.from_commit));
+    console.result(
+        command,
+        "rolled_back",
+        json!({
+            "from_commit": backup.to_commit,
+            "to_commit": backup.from_commit,
+            "from_version": backup.to_version,
+            "to_version": backup.from_version,
+        }),
+    );
+    Ok(Outcome::Success)
+}
 
-    let m = Arc::new(MultiProgress::new());
-    let failed_files = Arc::new(Mutex::new(Vec::new()));
-
-    let mut handles = vec![];
-
-    for (release, files) in download_batches {
-        let m = Arc::clone(&m);
-        let failed_files = Arc::clone(&failed_files);
-        let assets_dir = assets_dir.to_string();
-
-        let handle = std::thread::spawn(move || {
-            use regex::Regex;
-            files.par_iter().for_each(|(file, file_size)| {
-                let filename = Path::new(&file)
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or(&file);
-
-                let re_square = Regex::new(r"\[([^\]]+)\]").unwrap();
-                let replaced = re_square
-                    .replace_all(filename, |caps: &regex::Captures| format!(".{}.", &caps[1]));
-                let re_round = Regex::new(r"\(([^)]+)\)").unwrap();
-                let replaced = re_round.replace_all(&replaced, |caps: &regex::Captures| {
-                    format!(".{}.", &caps[1])
-                });
-                let replaced = replaced.replace("'", ".");
-                let replaced = replaced.replace(" ", ".");
-                let re_dot = Regex::new(r"\.+").unwrap();
-                let url_filename = re_dot.replace_all(&replaced, ".");
-
-                use std::time::Duration;
-
-                let pb = m.add(
-                    ProgressBar::new(*file_size)
-                        .with_style(
-                            ProgressStyle::default_bar()
-                                .template("{spinner:.green} {msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} {percent}%")
-                                .unwrap()
-                                .progress_chars("==-"),
-                        )
-                );
-                pb.set_message(format!("下载中: {}", filename));
-                let mut success = false;
-                for retry in 0..MAX_RETRY {
-                    let proxy = PROXY_LIST[(retry as usize) % PROXY_LIST.len()];
-
-                    if retry > 0 {
-                        pb.reset();
-                        pb.set_position(0);
-                        pb.set_message(format!("使用镜像{}重试中({}/{}) {}",proxy, retry + 1, MAX_RETRY, filename));
-                    }
-                    let url = format!("{proxy}/CrazySpottedDove/KingdomRushDove/releases/download/{release}/{url_filename}");
-                    let client = Client::builder()
-                        .danger_accept_invalid_certs(true)
-                        .timeout(Duration::from_secs(60))
-                        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36 Edg/141.0.0.0")
-                        .build()
-                        .unwrap();
-                    match client.get(&url).header("Accept", "*/*")
-                                .header("Accept-Language", "zh-CN,zh;q=0.9")
-                                .header("Connection", "keep-alive")
-                                .header("Sec-Fetch-Mode", "no-cors")
-                                .header("Sec-Fetch-Site", "none")
-                                .header("Sec-Fetch-User", "?1")
-                                .header("Upgrade-Insecure-Requests", "1").send() {
-                        Ok(mut response) if response.status().is_success() => {
-                            let fullpath = format!("{}/{}", assets_dir, file);
-                            if let Some(parent) = Path::new(&fullpath).parent() {
-                                let _ = fs::create_dir_all(parent);
-                            }
-                            let mut file_out = match fs::File::create(&fullpath) {
-                                Ok(f) => f,
-                                Err(e) => {
-                                    pb.finish_with_message(format!("写入失败: {}: {:?}", file, e));
-                                    failed_files.lock().unwrap().push(file.clone());
-                                    return;
-                                }
-                            };
-                            let mut downloaded: u64 = 0;
-                            let mut buf = [0u8; 16 * 1024];
-                            let mut last_check = std::time::Instant::now();
-                            let mut last_downloaded = 0u64;
-                            let mut slow_count = 0;
-                            loop {
-                                match response.read(&mut buf) {
-                                    Ok(0) => break,
-                                    Ok(n) => {
-                                        if file_out.write_all(&buf[..n]).is_err() {
-                                            pb.finish_with_message(format!("写入失败: {}", file));
-                                            failed_files.lock().unwrap().push(file.clone());
-                                            return;
-                                        }
-                                        downloaded += n as u64;
-                                        pb.set_position(downloaded);
-                                        let now = std::time::Instant::now();
-                                        if now.duration_since(last_check) >= SPEED_CHECK_INTERVAL {
-                                            let bytes = downloaded - last_downloaded;
-                                            let speed = bytes as u64 / SPEED_CHECK_INTERVAL.as_secs();
-                                            if speed < MIN_SPEED {
-                                                slow_count += 1;
-                                            } else {
-                                                slow_count = 0;
-                                            }
-                                            last_check = now;
-                                            last_downloaded = downloaded;
-                                            if slow_count >= 2 {
-                                                pb.set_message(format!("速度过慢，切换镜像..."));
-                                                break; // 主动中断，进入下一个重试
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        pb.finish_with_message(format!("下载失败: {}: {:?}", file, e));
-                                        break;
-                                    }
-                                }
-                            }
-                            pb.finish_with_message(format!("已完成: {}", file));
-                            success = true;
-                            break;
-                        }
-                        Ok(r) => {
-                            println!(
-                                "{RED}下载失败: {} 状态码: {}{RESET}",
-                                file,
-                                r.status()
-                            );
-                            // 状态异常，重试
-                            continue;
-                        }
-                        Err(e) => {
-                            // 请求失败，重试
-                            println!("{RED}请求失败: {} 错误: {:?}{RESET}", file, e);
-                            continue;
-                        }
-                    }
-                }
-                if !success {
-                    pb.finish_with_message(format!("请求失败: {}", file));
-                    failed_files.lock().unwrap().push(file.clone());
-                }
-            });
-        });
-        handles.push(handle);
+// 检查并安装新版更新程序，成功后以原参数重新启动，并以新进程的退出码退出。
+// 任何失败都只给出警告，继续用当前版本完成本次运行
+fn self_update(updater: &Updater, console: &Console) {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            console.warn(YELLOW, format!("无法定位更新程序自身，跳过自我更新：{e}"));
+            return;
+        }
+    };
+    updater.clean_self_update(&exe);
+    console.phase("self_update");
+    let current_version = env!("CARGO_PKG_VERSION");
+    let update = match updater.check_self_update(current_version) {
+        Ok(Some(update)) => update,
+        Ok(None) => return,
+        Err(e) => {
+            console.warn(YELLOW, format!("检查更新程序新版本失败：{e}"));
+            console.emit(json!({ "event": "self_update_failed", "message": e.to_string() }));
+            return;
+        }
+    };
+    console.say(
+        CYAN,
+        format!(
+            "发现新版更新程序 {current_version} → {}，正在下载……",
+            update.version
+        ),
+    );
+    console.emit(json!({
+        "event": "self_update_available",
+        "current_version": current_version,
+        "version": update.version,
+    }));
+    let installed = updater.install_self_update(&update, &exe);
+    console.clear();
+    if let Err(e) = installed {
+        console.warn(
+            YELLOW,
+            format!("更新程序自我更新失败，继续使用当前版本：{e}"),
+        );
+        console.emit(json!({ "event": "self_update_failed", "message": e.to_string() }));
+        return;
+    }
+    console.say(
+        GREEN,
+        format!("更新程序已更新到 {}，正在重新启动……", update.version),
+    );
+    console.emit(json!({ "event": "self_updated", "version": update.version }));
+    match std::process::Command::new(&exe)
+        .args(std::env::args_os().skip(1))
+        .env(SELF_UPDATED_ENV, &update.version)
+        .status()
+    {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => console.warn(YELLOW, format!("重新启动失败，本次继续使用当前版本：{e}")),
+    }
+}
+
+fn rank_mirrors(updater: &mut Updater, console: &Console) {
+    console.phase("probe_mirrors");
+    console.say(CYAN, "正在测试镜像速度……");
+    let results = updater.rank_mirrors();
+    console.emit(json!({
+        "event": "mirrors_ranked",
+        "mirrors": results
+            .iter()
+            .map(|r| json!({
+                "mirror": r.mirror,
+                "latency_ms": r.latency.map(|l| l.as_millis() as u64),
+                "error": r.error,
+            }))
+            .collect::<Vec<_>>(),
+    }));
+    match results.first() {
+        Some(best) if best.is_healthy() => console.say(
+            GREEN,
+            format!(
+                "将优先使用镜像 {}（{} ms）",
+                best.mirror,
+                best.latency.unwrap_or_default().as_millis()
+            ),
+        ),
+        _ => console.say(YELLOW, "所有镜像探测均失败，将按配置顺序尝试"),
     }
+}
 
-    for handle in handles {
-        let _ = handle.join();
+fn run_assets(cli: &Cli, updater: &Updater, console: &Console) -> Result<Outcome> {
+    console.phase("assets");
+    let result = updater.sync_assets();
+    console.clear();
+    match result {
+        Ok(report) if report.is_complete() => {
+            console.say(GREEN, "美术资源检查/更新完成！");
+            console.result(
+                cli.command.name(),
+                "assets_synced",
+                json!({ "assets": report.files.len(), "trashed": report.trashed }),
+            );
+            Ok(Outcome::Success)
+        }
+        Ok(report) => {
+            print_failed_assets(console, &report);
+            console.warn(RED, "资源检查/更新失败：部分资源文件下载失败");
+            console.result(
+                cli.command.name(),
+                "failed",
+                json!({ "failed_assets": failed_assets_json(&report) }),
+            );
+            Ok(Outcome::PartialFailure)
+        }
+        Err(e) => {
+            console.warn(RED, format!("资源检查/更新失败：{}", e));
+            Err(e)
+        }
     }
-    m.clear().unwrap();
+}
 
-    trash_unindexed_assets(&assets_index, &assets_dir, &trashed_dir)?;
+fn print_failed_assets(console: &Console, report: &AssetReport) {
+    console.warn(RED, "以下资源文件下载失败，未完成全部资源更新：");
+    for outcome in report.failed() {
+        console.warn(
+            RED,
+            format!(
+                "  - {}（尝试 {} 次）：{}",
+                outcome.file,
+                outcome.attempts,
+                outcome.error.as_deref().unwrap_or("")
+            ),
+        );
+    }
+}
 
-    let failed_files = Arc::try_unwrap(failed_files).unwrap().into_inner().unwrap();
-    if !failed_files.is_empty() {
-        eprintln!("{RED}以下资源文件下载失败，未完成全部资源更新：{RESET}");
-        for file in &failed_files {
-            eprintln!("{RED}  - {}{RESET}", file);
+fn print_verify_report(console: &Console, report: &VerifyReport) {
+    console.say(CYAN, format!("本地版本: {}", report.commit));
+    let sections = [
+        ("缺失的文件", &report.missing),
+        ("内容被修改的文件", &report.modified),
+        ("大小与索引不符的资源", &report.size_mismatched),
+        ("多余的文件", &report.extra),
+    ];
+    for (title, files) in sections {
+        if files.is_empty() {
+            continue;
+        }
+        console.say(RED, format!("{title}（{} 个）：", files.len()));
+        for file in files {
+            console.say(RED, format!("  - {file}"));
         }
-        return Err("部分资源文件下载失败".into());
     }
+    if report.is_clean() {
+        console.say(GREEN, "安装完整，所有文件均与当前版本一致。");
+    } else {
+        console.say(
+            YELLOW,
+            "可以运行 repair 修复缺失或损坏的代码文件，运行 assets 修复资源。",
+        );
+    }
+}
 
-    Ok(())
+fn plan_json(plan: &UpdatePlan) -> Value {
+    let files: Vec<Value> = plan
+        .files
+        .iter()
+        .map(|(action, path)| match action {
+            DiffAction::Added => json!({ "action": "added", "path": path }),
+            DiffAction::Modified => json!({ "action": "modified", "path": path }),
+            DiffAction::Removed => json!({ "action": "removed", "path": path }),
+            DiffAction::Renamed { from } => {
+                json!({ "action": "renamed", "path": path, "from": from })
+            }
+        })
+        .collect();
+    json!({
+        "event": "plan",
+        "mode": match plan.mode {
+            WorkingMode::Normal => "normal",
+            WorkingMode::Fix => "fix",
+            WorkingMode::Repair => "repair",
+        },
+        "from_commit": plan.from_commit,
+        "to_commit": plan.to_commit,
+        "messages": plan.messages.iter().map(|m| m.trim_end()).collect::<Vec<_>>(),
+        "files": files,
+        "untracked": plan.untracked,
+    })
 }
-use mlua::Lua;
 
-fn read_assets_index(path: &str) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
-    let content = std::fs::read_to_string(path)?;
-    let lua = Lua::new();
-    let table: mlua::Table = lua.load(&content).eval()?;
+fn asset_plan_json(assets: &AssetPlan) -> Value {
+    json!({
+        "count": assets.count(),
+        "total_bytes": assets.total_bytes(),
+        "releases": assets
+            .downloads
+            .iter()
+            .map(|(release, files)| json!({
+                "release": release,
+                "bytes": files.iter().map(|(_, size)| size).sum::<u64>(),
+                "files": files
+                    .iter()
+                    .map(|(file, size)| json!({ "file": file, "size": size }))
+                    .collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>(),
+        "trash": assets.trash,
+    })
+}
 
-    let mut index = HashMap::new();
-    for pair in table.pairs::<String, mlua::Table>() {
-        let (key, value_table) = pair?;
-        let size: u64 = value_table.get("size")?;
-        index.insert(key, size);
-    }
-    Ok(index)
+fn failed_assets_json(report: &AssetReport) -> Vec<Value> {
+    report
+        .failed()
+        .map(|outcome| {
+            json!({
+                "file": outcome.file,
+                "attempts": outcome.attempts,
+                "error": outcome.error,
+            })
+        })
+        .collect()
 }
 
-fn file_size(path: &str) -> u64 {
-    fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+// 读取本地版本记录，读取失败说明不是完整的安装
+fn installed_commit(updater: &Updater) -> Result<String> {
+    Ok(updater
+        .local_commit()
+        .map_err(|e| InvalidInstall(format!("无法读取本地版本记录：{e}")))?)
 }
 
-fn get_release_for_file(filename: &str) -> String {
-    // 去除扩展名（最后一个点及其后内容）
-    let name = match filename.rfind('.') {
-        Some(idx) => &filename[..idx],
-        None => filename,
-    };
-    let len = name.chars().count();
-    if len == 0 {
-        return "other".to_string();
-    }
-    // Lua: local mid = math.floor((len + 1) / 2)
-    let mid = (len + 1) / 2 - 1; // Rust 0-based
-    let ch = name.chars().nth(mid).unwrap_or('o').to_ascii_lowercase();
-    if ch.is_ascii_alphanumeric() {
-        ch.to_string()
+fn check_outcome(check: &CheckResult) -> Outcome {
+    if check.is_up_to_date() {
+        Outcome::UpToDate
     } else {
-        "other".to_string()
+        Outcome::UpdateAvailable
     }
 }
 
-fn trash_unindexed_assets(
-    index: &HashMap<String, u64>,
-    assets_dir: &str,
-    trashed_dir: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    for entry in fs::read_dir(assets_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let relpath = path
-                .strip_prefix(assets_dir)?
-                .to_str()
-                .unwrap_or("")
-                .to_string();
-            if relpath != "assets_index.lua" && !index.contains_key(&relpath) {
-                let trash_path = format!("{}/{}", trashed_dir, relpath);
-                if let Some(parent) = Path::new(&trash_path).parent() {
-                    fs::create_dir_all(parent)?;
-                }
-                fs::rename(&path, &trash_path)?;
-                println!("{YELLOW}多余文件已移至回收站: {}{RESET}", trash_path);
-            }
-        }
+fn is_current_dir_safe() -> bool {
+    let current_dir = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+    current_dir
+        .file_name()
+        .is_some_and(|dir_name| dir_name == WORK_DIR)
+}
+
+fn wait_for_enter() {
+    println!("{CYAN}按回车键退出...{RESET}");
+    let mut _wait = String::new();
+    std::io::stdin().read_line(&mut _wait).ok();
+}
+
+fn pause(cli: &Cli) {
+    if cli.should_pause() {
+        wait_for_enter();
     }
-    Ok(())
+}
+
+fn read_input() -> String {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).ok();
+    input.trim().to_string()
 }
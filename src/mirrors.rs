@@ -0,0 +1,102 @@
+// 镜像健康探测：并发测量各远程源解析最新版本的耗时，结合历史记录排序
+
+use crate::remote::{RemoteSource, is_unsupported};
+use crate::scores::MirrorScores;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(8);
+
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub mirror: String,
+    // 可用时为耗时
+    pub latency: Option<Duration>,
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    pub fn is_healthy(&self) -> bool {
+        self.latency.is_some()
+    }
+}
+
+// 并发探测所有远程源解析 branch 的耗时，结果与 sources 一一对应。
+// 超时未返回的视为不可用，被暂时拉黑的镜像不参与探测
+pub(crate) fn probe_sources(
+    sources: &[Arc<dyn RemoteSource>],
+    scores: &MirrorScores,
+    branch: &str,
+) -> Vec<ProbeResult> {
+    let (tx, rx) = mpsc::channel();
+    for (i, source) in sources.iter().enumerate() {
+        if scores.is_blacklisted(source.name()) {
+            continue;
+        }
+        let source = Arc::clone(source);
+        let tx = tx.clone();
+        let branch = branch.to_string();
+        // 不使用 scope，超时的探测线程留在后台自行结束
+        std::thread::spawn(move || {
+            let start = Instant::now();
+            let result = source.head_commit(&branch).map(|_| start.elapsed());
+            let _ = tx.send((i, result));
+        });
+    }
+    drop(tx);
+
+    let mut results: Vec<ProbeResult> = sources
+        .iter()
+        .map(|source| ProbeResult {
+            mirror: source.name().to_string(),
+            latency: None,
+            error: Some(if scores.is_blacklisted(source.name()) {
+                "近期连续失败，已暂时拉黑".to_string()
+            } else {
+                "探测超时".to_string()
+            }),
+        })
+        .collect();
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        let Ok((i, result)) = rx.recv_timeout(timeout) else {
+            break;
+        };
+        match result {
+            Ok(latency) => {
+                scores.record_success(&results[i].mirror);
+                results[i].latency = Some(latency);
+                results[i].error = None;
+            }
+            Err(e) if is_unsupported(&e) => {
+                results[i].error = Some("不支持探测".to_string());
+            }
+            Err(e) => {
+                scores.record_failure(&results[i].mirror);
+                results[i].error = Some(e.to_string());
+            }
+        }
+    }
+    results
+}
+
+// 可用的源排在前面，按探测耗时结合历史成功率与下载速度排序；
+// 其余保持原有顺序排在后面作为兜底
+pub(crate) fn rank(
+    sources: Vec<Arc<dyn RemoteSource>>,
+    results: Vec<ProbeResult>,
+    scores: &MirrorScores,
+) -> (Vec<Arc<dyn RemoteSource>>, Vec<ProbeResult>) {
+    let mut ranked: Vec<_> = sources.into_iter().zip(results).collect();
+    // sort_by 是稳定排序，不可用的源之间保持原有（即按历史记录排好的）顺序
+    ranked.sort_by(|(_, a), (_, b)| {
+        let cost = |result: &ProbeResult| {
+            result.latency.map_or(f64::INFINITY, |latency| {
+                scores.rank_cost(&result.mirror, latency)
+            })
+        };
+        cost(a).total_cmp(&cost(b))
+    });
+    ranked.into_iter().unzip()
+}
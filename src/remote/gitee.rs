@@ -0,0 +1,127 @@
+use super::{
+    CommitInfo, Comparison, Download, REPO_PATH, RemoteSource, commit_id, http_client,
+    parse_commits, parse_comparison, parse_tree, send_download,
+};
+use crate::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const GITEE: &str = "https://gitee.com";
+
+// Gitee 镜像仓库，提供版本、比较与文件树接口，也可以下载代码文件与 release 附件。
+// Gitee 对部分请求会返回登录页或风控页面而不是文件内容，这类响应通不过下载后的
+// blob 哈希、SHA-256 与签名校验，会被当作失败换下一个镜像
+pub struct GiteeSource {
+    base: String,
+}
+
+impl GiteeSource {
+    pub fn new() -> Self {
+        GiteeSource {
+            base: GITEE.to_string(),
+        }
+    }
+}
+
+impl Default for GiteeSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RemoteSource for GiteeSource {
+    fn name(&self) -> &str {
+        "gitee"
+    }
+
+    fn head_commit(&self, branch: &str) -> Result<String> {
+        let url = format!("{}/api/v5/repos/{REPO_PATH}/branches/{branch}", self.base);
+        let response = http_client()?.get(&url).send()?;
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        let j: Value = response.json()?;
+        let sha = j["commit"]["sha"]
+            .as_str()
+            .ok_or("Failed to parse remote commit hash")?;
+        commit_id(sha)
+    }
+
+    fn resolve_commit(&self, rev: &str) -> Result<String> {
+        let url = format!("{}/api/v5/repos/{REPO_PATH}/commits/{rev}", self.base);
+        let response = http_client()?.get(&url).send()?;
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        let j: Value = response.json()?;
+        let sha = j["sha"].as_str().ok_or("Failed to parse commit hash")?;
+        commit_id(sha)
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Result<Comparison> {
+        let url = format!(
+            "{}/api/v5/repos/{REPO_PATH}/compare/{from}...{to}",
+            self.base
+        );
+        // 直接传出 reqwest 的错误，调用方据此判断网络是否可达
+        let response = http_client()?.get(&url).send()?;
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        parse_comparison(&response.json::<Value>()?)
+    }
+
+    fn commits(&self, branch: &str, limit: usize) -> Result<Vec<CommitInfo>> {
+        let url = format!(
+            "{}/api/v5/repos/{REPO_PATH}/commits?sha={branch}&per_page={limit}",
+            self.base
+        );
+        let response = http_client()?.get(&url).send()?;
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        parse_commits(&response.json::<Value>()?)
+    }
+
+    fn tree(&self, rev: &str) -> Result<HashMap<String, String>> {
+        let url = format!(
+            "{}/api/v5/repos/{REPO_PATH}/git/trees/{rev}?recursive=1",
+            self.base
+        );
+        let response = http_client()?.get(&url).send()?;
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        parse_tree(&response.json::<Value>()?)
+    }
+
+    fn fetch_file(&self, rev: &str, path: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/{REPO_PATH}/raw/{rev}/{path}", self.base);
+        let response = http_client()?.get(&url).send()?;
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        Ok(response.bytes()?.to_vec())
+    }
+
+    fn fetch_asset(&self, tag: &str, name: &str, offset: u64) -> Result<Download> {
+        let url = format!("{}/{REPO_PATH}/releases/download/{tag}/{name}", self.base);
+        send_download(http_client()?.get(&url), offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::is_network_error;
+
+    #[test]
+    fn diff_keeps_network_errors() {
+        // 本机没有服务监听的端口，连接会被拒绝
+        let source = GiteeSource {
+            base: "http://127.0.0.1:9".to_string(),
+        };
+        let e = source.diff("a", "b").err().unwrap();
+        assert!(is_network_error(&e), "{e}");
+    }
+}
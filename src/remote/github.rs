@@ -0,0 +1,79 @@
+use super::{Download, REPO_PATH, RemoteSource, commit_id, http_client, send_download};
+use crate::Result;
+use serde_json::Value;
+use std::time::Duration;
+
+// 通过 GitHub 网页镜像访问仓库，base 形如 https://bgithub.xyz
+pub struct GitHubMirror {
+    base: String,
+}
+
+impl GitHubMirror {
+    pub fn new(base: impl Into<String>) -> Self {
+        GitHubMirror {
+            base: base.into().trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl RemoteSource for GitHubMirror {
+    fn name(&self) -> &str {
+        &self.base
+    }
+
+    fn head_commit(&self, branch: &str) -> Result<String> {
+        let url = format!(
+            "{}/{REPO_PATH}/commits/deferred_commit_data/{branch}?original_branch={branch}",
+            self.base
+        );
+        let response = http_client()?
+            .get(url)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("X-Requested-With", "XMLHttpRequest")
+            .send()?;
+
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        let json: Value = serde_json::from_str(&response.text()?)?;
+        let deferred_commits = json["deferredCommits"]
+            .as_array()
+            .ok_or("Failed to parse 'deferredCommits' array")?;
+
+        // 获取第一个 commit 的 oid
+        let remote_commit_hash = deferred_commits
+            .first()
+            .and_then(|commit| commit["oid"].as_str())
+            .ok_or("Failed to parse remote commit hash")?;
+        commit_id(remote_commit_hash)
+    }
+
+    fn fetch_file(&self, rev: &str, path: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/{REPO_PATH}/raw/{rev}/{path}", self.base);
+        let response = http_client()?.get(&url).send()?;
+        if !response.status().is_success() {
+            return Err(format!("状态码: {}", response.status()).into());
+        }
+        Ok(response.bytes()?.to_vec())
+    }
+
+    fn fetch_asset(&self, tag: &str, name: &str, offset: u64) -> Result<Download> {
+        let url = format!("{}/{REPO_PATH}/releases/download/{tag}/{name}", self.base);
+        let client = reqwest::blocking::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .timeout(Duration::from_secs(60))
+            .user_agent(super::USER_AGENT)
+            .build()?;
+        let request = client
+            .get(&url)
+            .header("Accept", "*/*")
+            .header("Accept-Language", "zh-CN,zh;q=0.9")
+            .header("Connection", "keep-alive")
+            .header("Sec-Fetch-Mode", "no-cors")
+            .header("Sec-Fetch-Site", "none")
+            .header("Sec-Fetch-User", "?1")
+            .header("Upgrade-Insecure-Requests", "1");
+        send_download(request, offset)
+    }
+}
@@ -0,0 +1,103 @@
+use super::{
+    CommitInfo, Comparison, Download, RemoteSource, commit_id, parse_commits, parse_comparison,
+    parse_tree,
+};
+use crate::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Seek, SeekFrom};
+use std::path::PathBuf;
+
+// 本地目录形式的远程源，可用于离线测试或充当本地镜像。目录结构：
+//
+//   heads/<branch>               内容为该分支最新的 commit
+//   tags/<tag>                   内容为该标签指向的 commit
+//   compare/<from>...<to>.json   与 Gitee 比较接口格式相同
+//   commits/<branch>.json        与 Gitee 提交列表接口格式相同，从新到旧
+//   trees/<rev>.json             与 Gitee 文件树接口格式相同
+//   raw/<rev>/<path>             代码文件
+//   releases/<tag>/<name>        release 附件
+pub struct LocalSource {
+    root: PathBuf,
+    name: String,
+}
+
+impl LocalSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        LocalSource {
+            name: format!("file://{}", root.display()),
+            root,
+        }
+    }
+
+    // 接受 file:// 地址或普通目录路径
+    pub fn from_url(url: &str) -> Result<Self> {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        let root = PathBuf::from(path);
+        if !root.is_dir() {
+            return Err(format!("本地远程源目录不存在: {}", root.display()).into());
+        }
+        Ok(Self::new(root))
+    }
+}
+
+impl RemoteSource for LocalSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn head_commit(&self, branch: &str) -> Result<String> {
+        let head = fs::read_to_string(self.root.join("heads").join(branch))?;
+        commit_id(&head)
+    }
+
+    // 依次查找标签与分支，都不存在时视为 commit
+    fn resolve_commit(&self, rev: &str) -> Result<String> {
+        for dir in ["tags", "heads"] {
+            if let Ok(commit) = fs::read_to_string(self.root.join(dir).join(rev)) {
+                return commit_id(&commit);
+            }
+        }
+        commit_id(rev).map_err(|_| format!("找不到分支、标签或提交: {rev}").into())
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Result<Comparison> {
+        let path = self
+            .root
+            .join("compare")
+            .join(format!("{from}...{to}.json"));
+        let j: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        parse_comparison(&j)
+    }
+
+    fn commits(&self, branch: &str, limit: usize) -> Result<Vec<CommitInfo>> {
+        let path = self.root.join("commits").join(format!("{branch}.json"));
+        let j: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let mut commits = parse_commits(&j)?;
+        commits.truncate(limit);
+        Ok(commits)
+    }
+
+    fn tree(&self, rev: &str) -> Result<HashMap<String, String>> {
+        let path = self.root.join("trees").join(format!("{rev}.json"));
+        let j: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        parse_tree(&j)
+    }
+
+    fn fetch_file(&self, rev: &str, path: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.root.join("raw").join(rev).join(path))?)
+    }
+
+    fn fetch_asset(&self, tag: &str, name: &str, offset: u64) -> Result<Download> {
+        let mut file = fs::File::open(self.root.join("releases").join(tag).join(name))?;
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(offset.min(len)))?;
+        Ok(Download {
+            content_length: Some(len.saturating_sub(offset)),
+            resumed: true,
+            reader: Box::new(file),
+        })
+    }
+}
@@ -0,0 +1,499 @@
+// 远程源抽象。更新引擎只通过 `RemoteSource` 访问远程仓库，
+// 具体是 GitHub 镜像、Gitee 还是本地目录由调用方决定。
+
+mod gitee;
+mod github;
+mod local;
+
+pub use gitee::GiteeSource;
+pub use github::GitHubMirror;
+pub use local::LocalSource;
+
+use crate::Result;
+use crate::fsutil::safe_relative_path;
+use crate::scores::MirrorScores;
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+
+pub(crate) const MAX_RETRY: u64 = 3;
+pub(crate) const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36 Edg/141.0.0.0";
+pub(crate) const REPO_PATH: &str = "CrazySpottedDove/KingdomRushDove";
+pub(crate) const DEFAULT_BRANCH: &str = "master";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffAction {
+    Added,
+    Modified,
+    Removed,
+    // 从 from 改名而来，旧路径需要删除
+    Renamed { from: String },
+}
+
+pub type DiffRecord = (DiffAction, String);
+
+// 两个提交之间的比较结果
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    // 按时间顺序排列的提交信息
+    pub messages: Vec<String>,
+    pub files: Vec<DiffRecord>,
+    // 目标版本中各文件的 git blob 哈希，用于校验下载内容。接口未提供时为空
+    pub blobs: HashMap<String, String>,
+    // 两个提交的合并基础。与 from 不同时说明 from 不是 to 的祖先（切换通道或回退），
+    // 此时 files 只包含合并基础之后 to 一侧的改动。接口未提供时为 None
+    pub merge_base: Option<String>,
+}
+
+// 一个远程提交的摘要
+#[derive(Debug, Clone, Default)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub author: String,
+    // 接口给出的 ISO 8601 时间
+    pub date: String,
+    pub message: String,
+}
+
+impl CommitInfo {
+    pub fn short_sha(&self) -> &str {
+        self.sha.get(..7).unwrap_or(&self.sha)
+    }
+
+    // 提交信息的第一行
+    pub fn summary(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+}
+
+// 一个正在下载的远程文件
+pub struct Download {
+    pub reader: Box<dyn Read + Send>,
+    // 本次响应的长度（续传时为剩余部分的长度）
+    pub content_length: Option<u64>,
+    // 是否从请求的偏移处续传；为 false 时数据从文件开头开始
+    pub resumed: bool,
+}
+
+// 远程源不支持某项操作时返回此错误，调用方会跳过该源
+#[derive(Debug)]
+pub struct Unsupported {
+    pub source: String,
+    pub operation: &'static str,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "远程源 {} 不支持 {}", self.source, self.operation)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+// 远程源上不存在请求的文件，而不是镜像本身出了问题
+#[derive(Debug)]
+pub struct NotFound;
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("远程文件不存在")
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+pub(crate) fn is_not_found(e: &crate::Error) -> bool {
+    e.downcast_ref::<NotFound>().is_some()
+        || e.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+pub trait RemoteSource: Send + Sync {
+    fn name(&self) -> &str;
+
+    // 解析分支最新的 commit
+    fn head_commit(&self, branch: &str) -> Result<String> {
+        let _ = branch;
+        Err(self.unsupported("head_commit"))
+    }
+
+    // 把提交、标签或分支名解析为 commit。默认按分支的方式解析，
+    // 对于能接受任意引用的网页镜像已经足够
+    fn resolve_commit(&self, rev: &str) -> Result<String> {
+        self.head_commit(rev)
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Result<Comparison> {
+        let _ = (from, to);
+        Err(self.unsupported("diff"))
+    }
+
+    // branch 上最近的 limit 个提交，从新到旧
+    fn commits(&self, branch: &str, limit: usize) -> Result<Vec<CommitInfo>> {
+        let _ = (branch, limit);
+        Err(self.unsupported("commits"))
+    }
+
+    // rev 版本的完整文件树，返回 路径 → git blob 哈希
+    fn tree(&self, rev: &str) -> Result<HashMap<String, String>> {
+        let _ = rev;
+        Err(self.unsupported("tree"))
+    }
+
+    // rev 可以是分支名或 commit
+    fn fetch_file(&self, rev: &str, path: &str) -> Result<Vec<u8>> {
+        let _ = (rev, path);
+        Err(self.unsupported("fetch_file"))
+    }
+
+    // name 为 release 附件名（已按 GitHub 规则转义），offset 大于 0 时请求从该处续传
+    fn fetch_asset(&self, tag: &str, name: &str, offset: u64) -> Result<Download> {
+        let _ = (tag, name, offset);
+        Err(self.unsupported("fetch_asset"))
+    }
+
+    fn unsupported(&self, operation: &'static str) -> crate::Error {
+        Box::new(Unsupported {
+            source: self.name().to_string(),
+            operation,
+        })
+    }
+}
+
+// 远程返回的 commit 会用作暂存与备份的目录名，并写入本地版本记录，
+// 只接受 40 位十六进制的完整哈希
+pub(crate) fn commit_id(sha: &str) -> Result<String> {
+    let sha = sha.trim();
+    if sha.len() == 40 && sha.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(sha.to_string())
+    } else {
+        Err(format!("拒绝无效的 commit: {sha:?}").into())
+    }
+}
+
+pub(crate) fn is_unsupported(e: &crate::Error) -> bool {
+    e.downcast_ref::<Unsupported>().is_some()
+}
+
+// 错误是否由网络不可达引起（连接失败、超时等），而不是远程内容有问题
+pub fn is_network_error(e: &crate::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e.as_ref());
+    while let Some(err) = source {
+        if let Some(e) = err.downcast_ref::<reqwest::Error>()
+            && (e.is_connect() || e.is_timeout())
+        {
+            return true;
+        }
+        if let Some(e) = err.downcast_ref::<std::io::Error>()
+            && matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::HostUnreachable
+                    | std::io::ErrorKind::NetworkUnreachable
+            )
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+// 根据地址创建远程源：
+// `gitee` 为 Gitee，`file://` 或本地目录为 LocalSource，其余 http(s) 地址视为 GitHub 镜像
+pub fn source_from_url(url: &str) -> Result<Arc<dyn RemoteSource>> {
+    if url.eq_ignore_ascii_case("gitee") {
+        return Ok(Arc::new(GiteeSource::new()));
+    }
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Ok(Arc::new(GitHubMirror::new(url)));
+    }
+    Ok(Arc::new(LocalSource::from_url(url)?))
+}
+
+// 按顺序创建各地址的远程源。GitHub 镜像不提供差分与文件树，列表中有 GitHub 镜像
+// 而没有 gitee 时在末尾补上 Gitee，替换镜像列表不会让 diff、tree 与提交记录失效
+pub fn sources_from_urls<'a>(
+    urls: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<Arc<dyn RemoteSource>>> {
+    let urls: Vec<&str> = urls.into_iter().collect();
+    let mut sources = urls
+        .iter()
+        .map(|url| source_from_url(url))
+        .collect::<Result<Vec<_>>>()?;
+    let has_mirror = urls
+        .iter()
+        .any(|url| url.starts_with("http://") || url.starts_with("https://"));
+    if has_mirror && !urls.iter().any(|url| url.eq_ignore_ascii_case("gitee")) {
+        sources.push(Arc::new(GiteeSource::new()));
+    }
+    Ok(sources)
+}
+
+// 按顺序轮流使用各个远程源，跳过不支持该操作的源，最多实际尝试 MAX_RETRY 次
+pub(crate) fn with_retry<T>(
+    sources: &[Arc<dyn RemoteSource>],
+    scores: &MirrorScores,
+    mut op: impl FnMut(&dyn RemoteSource) -> Result<T>,
+    mut on_fail: impl FnMut(&dyn RemoteSource, &crate::Error),
+) -> Result<T> {
+    let mut last_err = None;
+    let mut attempts = 0;
+    for source in retry_order(sources) {
+        if attempts >= MAX_RETRY {
+            break;
+        }
+        match op(source.as_ref()) {
+            Ok(value) => {
+                scores.record_success(source.name());
+                return Ok(value);
+            }
+            Err(e) if is_unsupported(&e) => continue,
+            Err(e) => {
+                attempts += 1;
+                scores.record_failure(source.name());
+                on_fail(source.as_ref(), &e);
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| "没有可用的远程源支持此操作".into()))
+}
+
+pub(crate) fn retry_order(
+    sources: &[Arc<dyn RemoteSource>],
+) -> impl Iterator<Item = &Arc<dyn RemoteSource>> {
+    sources
+        .iter()
+        .cycle()
+        .take(sources.len() * MAX_RETRY as usize)
+}
+
+// 发送可续传的下载请求，offset 大于 0 时附带 Range 头
+pub(crate) fn send_download(
+    request: reqwest::blocking::RequestBuilder,
+    offset: u64,
+) -> Result<Download> {
+    let request = if offset > 0 {
+        request.header("Range", format!("bytes={offset}-"))
+    } else {
+        request
+    };
+    let response = request.send()?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(Box::new(NotFound));
+    }
+    if !response.status().is_success() {
+        return Err(format!("状态码: {}", response.status()).into());
+    }
+    Ok(Download {
+        content_length: response.content_length(),
+        resumed: offset > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT,
+        reader: Box::new(response),
+    })
+}
+
+pub(crate) fn http_client() -> Result<Client> {
+    Ok(Client::builder()
+        .danger_accept_invalid_certs(true)
+        .user_agent(USER_AGENT)
+        .build()?)
+}
+
+// 解析 Gitee 比较接口格式的 json
+pub(crate) fn parse_comparison(j: &Value) -> Result<Comparison> {
+    let commits = j["commits"].as_array().ok_or("比较结果缺少 commits 字段")?;
+    let mut messages = commits
+        .iter()
+        .map(|c| parse_commit(c).message)
+        .collect::<Vec<String>>();
+    messages.reverse();
+    let files = j["files"].as_array().ok_or("比较结果缺少 files 字段")?;
+    let mut blobs = HashMap::new();
+    let files = files
+        .iter()
+        .map(|f| {
+            let filename = safe_relative_path(f["filename"].as_str().unwrap_or(""))?;
+            let diff_action = match f["status"].as_str().unwrap_or("") {
+                // 复制出的文件与新增无异，原文件保留
+                "added" | "copied" => DiffAction::Added,
+                "modified" => DiffAction::Modified,
+                "removed" => DiffAction::Removed,
+                "renamed" => match f["previous_filename"].as_str() {
+                    Some(from) if !from.is_empty() => {
+                        let from = safe_relative_path(from)?;
+                        if from == filename {
+                            DiffAction::Modified
+                        } else {
+                            DiffAction::Renamed { from }
+                        }
+                    }
+                    _ => DiffAction::Modified,
+                },
+                _ => DiffAction::Modified,
+            };
+            // 被删除文件的 sha 是旧版本的内容，用不上
+            if diff_action != DiffAction::Removed
+                && let Some(sha) = f["sha"].as_str().filter(|s| !s.is_empty())
+            {
+                blobs.insert(filename.clone(), sha.to_string());
+            }
+            Ok((diff_action, filename))
+        })
+        .collect::<std::result::Result<Vec<DiffRecord>, String>>()?;
+    Ok(Comparison {
+        messages,
+        files,
+        blobs,
+        merge_base: j["merge_base_commit"]["sha"].as_str().map(str::to_string),
+    })
+}
+
+// 解析 Gitee 提交列表接口格式的 json
+pub(crate) fn parse_commits(j: &Value) -> Result<Vec<CommitInfo>> {
+    let commits = j.as_array().ok_or("提交列表格式错误")?;
+    Ok(commits.iter().map(parse_commit).collect())
+}
+
+// 提交列表与比较结果中的单个提交格式相同
+fn parse_commit(c: &Value) -> CommitInfo {
+    let field = |v: &Value| v.as_str().unwrap_or("").to_string();
+    CommitInfo {
+        sha: field(&c["sha"]),
+        author: field(&c["commit"]["author"]["name"]),
+        date: field(&c["commit"]["author"]["date"]),
+        message: field(&c["commit"]["message"]),
+    }
+}
+
+// 解析 Gitee 文件树接口（recursive=1）格式的 json，只保留文件
+pub(crate) fn parse_tree(j: &Value) -> Result<HashMap<String, String>> {
+    if j["truncated"].as_bool() == Some(true) {
+        return Err("文件树过大，接口返回的结果被截断".into());
+    }
+    let entries = j["tree"].as_array().ok_or("文件树缺少 tree 字段")?;
+    let mut tree = HashMap::new();
+    for entry in entries {
+        if entry["type"].as_str() != Some("blob") {
+            continue;
+        }
+        let (Some(path), Some(sha)) = (entry["path"].as_str(), entry["sha"].as_str()) else {
+            continue;
+        };
+        tree.insert(safe_relative_path(path)?, sha.to_string());
+    }
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn sources_from_urls_appends_gitee_after_mirrors() {
+        let names = |urls: &[&str]| -> Vec<String> {
+            sources_from_urls(urls.iter().copied())
+                .unwrap()
+                .iter()
+                .map(|source| source.name().to_string())
+                .collect()
+        };
+        let mirrors = names(&["https://a.example", "https://b.example"]);
+        assert_eq!(mirrors.len(), 3);
+        assert_eq!(mirrors[2], "gitee");
+        let explicit = names(&["gitee", "https://a.example"]);
+        assert_eq!(explicit.len(), 2);
+        assert_eq!(explicit[0], "gitee");
+        // 本地目录自己提供差分
+        let dir = std::env::temp_dir().to_string_lossy().into_owned();
+        assert_eq!(names(&[dir.as_str()]).len(), 1);
+    }
+
+    #[test]
+    fn commit_id_accepts_only_full_hex_ids() {
+        assert_eq!(commit_id(SHA).unwrap(), SHA);
+        assert_eq!(commit_id(&format!("{SHA}\n")).unwrap(), SHA);
+        for sha in [
+            "",
+            "0123abc",
+            "../../victim",
+            &SHA.replace('a', "g"),
+            &format!("{SHA}0"),
+        ] {
+            assert!(commit_id(sha).is_err(), "{sha:?}");
+        }
+    }
+
+    #[test]
+    fn parse_comparison_maps_statuses() {
+        let j = json!({
+            "commits": [
+                { "sha": "1", "commit": { "message": "older" } },
+                { "sha": "2", "commit": { "message": "newer" } },
+            ],
+            "files": [
+                { "filename": "lua/added.lua", "status": "added", "sha": "b1" },
+                { "filename": "lua/copied.lua", "status": "copied", "sha": "b2" },
+                { "filename": "lua/modified.lua", "status": "modified", "sha": "b3" },
+                { "filename": "lua/removed.lua", "status": "removed", "sha": "b4" },
+                { "filename": "lua/new.lua", "status": "renamed", "previous_filename": "lua/old.lua", "sha": "b5" },
+                { "filename": "lua/same.lua", "status": "renamed", "previous_filename": "lua/same.lua" },
+                { "filename": "lua\\win.lua", "status": "changed" },
+            ],
+            "merge_base_commit": { "sha": SHA },
+        });
+        let comparison = parse_comparison(&j).unwrap();
+        assert_eq!(comparison.messages, ["newer", "older"]);
+        assert_eq!(
+            comparison.files,
+            vec![
+                (DiffAction::Added, "lua/added.lua".to_string()),
+                (DiffAction::Added, "lua/copied.lua".to_string()),
+                (DiffAction::Modified, "lua/modified.lua".to_string()),
+                (DiffAction::Removed, "lua/removed.lua".to_string()),
+                (
+                    DiffAction::Renamed {
+                        from: "lua/old.lua".to_string()
+                    },
+                    "lua/new.lua".to_string()
+                ),
+                (DiffAction::Modified, "lua/same.lua".to_string()),
+                (DiffAction::Modified, "lua/win.lua".to_string()),
+            ]
+        );
+        // 被删除文件的哈希不记录
+        assert_eq!(comparison.blobs.len(), 4);
+        assert!(!comparison.blobs.contains_key("lua/removed.lua"));
+        assert_eq!(comparison.merge_base.as_deref(), Some(SHA));
+    }
+
+    #[test]
+    fn parse_comparison_rejects_unsafe_paths() {
+        for (filename, previous) in [
+            ("../evil.lua", None),
+            ("/evil.lua", None),
+            ("C:/evil.lua", None),
+            ("lua/new.lua", Some("../../evil.lua")),
+        ] {
+            let j = json!({
+                "commits": [],
+                "files": [{
+                    "filename": filename,
+                    "status": if previous.is_some() { "renamed" } else { "added" },
+                    "previous_filename": previous,
+                }],
+            });
+            assert!(parse_comparison(&j).is_err(), "{filename:?} {previous:?}");
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// 各镜像的可靠性记录，保存在 _updater/mirror_scores.json 中跨次运行使用。
+// 连续失败过多的镜像会在一段时间内被降到最后、且不再参与探测。
+
+use crate::Result;
+use crate::fsutil;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const SCORES_FILE: &str = "mirror_scores.json";
+const BLACKLIST_FAILURES: u64 = 3;
+const BLACKLIST_DURATION: Duration = Duration::from_secs(30 * 60);
+// 排序时按下载这么多数据估算传输耗时，与探测延迟相加
+const RANK_TRANSFER_BYTES: f64 = 1024.0 * 1024.0;
+// 成功率低于此值时按此值计算，避免除以 0
+const MIN_SUCCESS_RATE: f64 = 0.05;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MirrorScore {
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u64,
+    // 资源下载累计的字节数与耗时，用于计算平均速度
+    pub bytes: u64,
+    pub seconds: f64,
+    // 最近一次失败的 unix 时间戳
+    pub last_failure: Option<u64>,
+}
+
+impl MirrorScore {
+    pub fn success_rate(&self) -> Option<f64> {
+        let total = self.successes + self.failures;
+        (total > 0).then(|| self.successes as f64 / total as f64)
+    }
+
+    // 平均下载速度，字节每秒
+    pub fn throughput(&self) -> Option<f64> {
+        (self.seconds > 0.0).then(|| self.bytes as f64 / self.seconds)
+    }
+
+    pub fn is_blacklisted(&self) -> bool {
+        self.consecutive_failures >= BLACKLIST_FAILURES
+            && self
+                .last_failure
+                .is_some_and(|t| unix_now().saturating_sub(t) < BLACKLIST_DURATION.as_secs())
+    }
+
+    fn from_json(v: &Value) -> Self {
+        MirrorScore {
+            successes: v["successes"].as_u64().unwrap_or(0),
+            failures: v["failures"].as_u64().unwrap_or(0),
+            consecutive_failures: v["consecutive_failures"].as_u64().unwrap_or(0),
+            bytes: v["bytes"].as_u64().unwrap_or(0),
+            seconds: v["seconds"].as_f64().unwrap_or(0.0),
+            last_failure: v["last_failure"].as_u64(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "successes": self.successes,
+            "failures": self.failures,
+            "consecutive_failures": self.consecutive_failures,
+            "bytes": self.bytes,
+            "seconds": self.seconds,
+            "last_failure": self.last_failure,
+        })
+    }
+}
+
+pub(crate) struct MirrorScores {
+    path: PathBuf,
+    scores: Mutex<HashMap<String, MirrorScore>>,
+}
+
+impl MirrorScores {
+    // 文件不存在或损坏时从空记录开始
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let scores = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+            .and_then(|j| {
+                j.as_object().map(|mirrors| {
+                    mirrors
+                        .iter()
+                        .map(|(mirror, v)| (mirror.clone(), MirrorScore::from_json(v)))
+                        .collect()
+                })
+            })
+            .unwrap_or_default();
+        MirrorScores {
+            path,
+            scores: Mutex::new(scores),
+        }
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        let j: serde_json::Map<String, Value> = self
+            .scores
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(mirror, score)| (mirror.clone(), score.to_json()))
+            .collect();
+        fsutil::write_atomic(&self.path, serde_json::to_string_pretty(&j)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, mirror: &str) -> MirrorScore {
+        self.scores
+            .lock()
+            .unwrap()
+            .get(mirror)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn is_blacklisted(&self, mirror: &str) -> bool {
+        self.get(mirror).is_blacklisted()
+    }
+
+    pub(crate) fn record_success(&self, mirror: &str) {
+        let mut scores = self.scores.lock().unwrap();
+        let score = scores.entry(mirror.to_string()).or_default();
+        score.successes += 1;
+        score.consecutive_failures = 0;
+    }
+
+    pub(crate) fn record_transfer(&self, mirror: &str, bytes: u64, elapsed: Duration) {
+        let mut scores = self.scores.lock().unwrap();
+        let score = scores.entry(mirror.to_string()).or_default();
+        score.bytes += bytes;
+        score.seconds += elapsed.as_secs_f64();
+    }
+
+    pub(crate) fn record_failure(&self, mirror: &str) {
+        let mut scores = self.scores.lock().unwrap();
+        let score = scores.entry(mirror.to_string()).or_default();
+        score.failures += 1;
+        score.consecutive_failures += 1;
+        score.last_failure = Some(unix_now());
+    }
+
+    // 未被拉黑的排在前面，按历史成功率从高到低；没有记录的视为可靠
+    pub(crate) fn sort_key(&self, mirror: &str) -> (bool, u64) {
+        let score = self.get(mirror);
+        let failure_rate = 1.0 - score.success_rate().unwrap_or(1.0);
+        (score.is_blacklisted(), (failure_rate * 1000.0) as u64)
+    }
+
+    // 探测可用的镜像的排序代价，越小越靠前：探测延迟加上按历史速度下载 1 MiB 的耗时，
+    // 再除以历史成功率。没有下载记录的镜像只按延迟计算
+    pub(crate) fn rank_cost(&self, mirror: &str, latency: Duration) -> f64 {
+        let score = self.get(mirror);
+        let transfer = score
+            .throughput()
+            .filter(|&t| t > 0.0)
+            .map_or(0.0, |t| RANK_TRANSFER_BYTES / t);
+        let success_rate = score.success_rate().unwrap_or(1.0).max(MIN_SUCCESS_RATE);
+        (latency.as_secs_f64() + transfer) / success_rate
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores() -> MirrorScores {
+        MirrorScores::load(PathBuf::from("krdove-scores-does-not-exist.json"))
+    }
+
+    #[test]
+    fn blacklists_after_consecutive_failures() {
+        let scores = scores();
+        for _ in 0..BLACKLIST_FAILURES - 1 {
+            scores.record_failure("a");
+        }
+        assert!(!scores.is_blacklisted("a"));
+        scores.record_failure("a");
+        assert!(scores.is_blacklisted("a"));
+        // 一次成功即解除
+        scores.record_success("a");
+        assert!(!scores.is_blacklisted("a"));
+    }
+
+    #[test]
+    fn blacklist_expires() {
+        let score = MirrorScore {
+            consecutive_failures: BLACKLIST_FAILURES,
+            last_failure: Some(unix_now() - BLACKLIST_DURATION.as_secs() - 1),
+            ..Default::default()
+        };
+        assert!(!score.is_blacklisted());
+    }
+
+    #[test]
+    fn sort_key_puts_reliable_mirrors_first() {
+        let scores = scores();
+        scores.record_success("good");
+        scores.record_success("flaky");
+        scores.record_failure("flaky");
+        for _ in 0..BLACKLIST_FAILURES {
+            scores.record_failure("dead");
+        }
+        let mut mirrors = ["dead", "flaky", "good", "unknown"];
+        mirrors.sort_by_key(|m| scores.sort_key(m));
+        assert_eq!(mirrors, ["good", "unknown", "flaky", "dead"]);
+    }
+
+    #[test]
+    fn rank_cost_combines_latency_reliability_and_speed() {
+        let scores = scores();
+        let latency = Duration::from_millis(100);
+        // 没有记录时只按延迟计算
+        assert_eq!(scores.rank_cost("new", latency), 0.1);
+        // 一半请求失败的镜像代价翻倍
+        scores.record_success("flaky");
+        scores.record_failure("flaky");
+        assert_eq!(scores.rank_cost("flaky", latency), 0.2);
+        // 1 MiB/s 的镜像额外计入下载 1 MiB 的 1 秒
+        scores.record_transfer("slow", 1024 * 1024, Duration::from_secs(1));
+        assert_eq!(scores.rank_cost("slow", latency), 1.1);
+        assert!(scores.rank_cost("new", latency) < scores.rank_cost("slow", latency));
+    }
+}
@@ -0,0 +1,185 @@
+// 更新程序的自我更新。仓库的 updater release 中附带清单 updater_manifest.json：
+//
+//   {
+//       "version": "0.2.0",
+//       "builds": {
+//           "windows-x86_64": { "name": "KingdomRushDoveUpdater.exe", "size": 123456, "sha256": "..." }
+//       }
+//   }
+//
+// builds 的键为 `<操作系统>-<架构>`。镜像是第三方服务，且不校验证书，因此清单旁还需附带
+// minisign 签名 updater_manifest.json.minisig，用构建时内置的公钥验证通过后才使用清单；
+// 新版本与美术资源走相同的镜像下载流程，并按已签名清单中的 SHA-256 校验，
+// 之后把正在运行的可执行文件改名为 <exe>.old，再把新文件改名到原位置。
+
+use crate::Result;
+use crate::fsutil::{self, TMP_SUFFIX};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub(crate) const UPDATER_RELEASE: &str = "updater";
+pub(crate) const MANIFEST_NAME: &str = "updater_manifest.json";
+pub(crate) const SIGNATURE_NAME: &str = "updater_manifest.json.minisig";
+// 验证清单签名的 minisign 公钥（base64），发布构建时由环境变量提供。
+// 没有公钥的构建无法验证清单，不进行自我更新
+pub(crate) const PUBLIC_KEY: Option<&str> = option_env!("KRDOVE_UPDATER_PUBLIC_KEY");
+// 新版本下载到 _updater/self_update/ 中
+pub(crate) const SELF_UPDATE_DIR: &str = "self_update";
+const OLD_SUFFIX: &str = ".old";
+
+// 可用的新版更新程序
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfUpdate {
+    pub version: String,
+    // release 附件名
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// 用 public_key 验证清单的 minisign 签名
+pub(crate) fn verify_manifest(content: &[u8], signature: &[u8], public_key: &str) -> Result<()> {
+    let public_key = minisign_verify::PublicKey::from_base64(public_key)?;
+    let signature = minisign_verify::Signature::decode(std::str::from_utf8(signature)?)?;
+    public_key
+        .verify(content, &signature, false)
+        .map_err(|e| format!("更新程序清单签名无效: {e}"))?;
+    Ok(())
+}
+
+// 当前平台在清单中的构建比 current_version 新时返回它
+pub(crate) fn parse_manifest(content: &[u8], current_version: &str) -> Result<Option<SelfUpdate>> {
+    let j: Value = serde_json::from_slice(content)?;
+    let version = j["version"]
+        .as_str()
+        .ok_or("更新程序清单缺少 version 字段")?;
+    if !is_newer(version, current_version) {
+        return Ok(None);
+    }
+    let platform = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+    let build = &j["builds"][&platform];
+    if build.is_null() {
+        return Ok(None);
+    }
+    let name = build["name"].as_str().ok_or("更新程序清单缺少 name 字段")?;
+    // 附件名会作为文件名使用，不允许带目录
+    if fsutil::safe_relative_path(name)? != name || name.contains('/') {
+        return Err(format!("拒绝不安全的附件名: {name:?}").into());
+    }
+    Ok(Some(SelfUpdate {
+        version: version.to_string(),
+        name: name.to_string(),
+        size: build["size"].as_u64().ok_or("更新程序清单缺少 size 字段")?,
+        // 自我更新必须校验内容，没有哈希的构建不使用
+        sha256: build["sha256"]
+            .as_str()
+            .ok_or("更新程序清单缺少 sha256 字段")?
+            .to_string(),
+    }))
+}
+
+// 按点分隔的数字逐段比较，非数字部分视为 0
+fn is_newer(candidate: &str, current: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(candidate) > parse(current)
+}
+
+pub(crate) fn old_executable(exe: &Path) -> PathBuf {
+    fsutil::sibling_with_suffix(exe, OLD_SUFFIX)
+}
+
+// 把已校验的新版本换到 exe 的位置。先复制到 exe 旁边，保证最后两次改名在同一目录内完成；
+// 正在运行的文件可以改名但不能删除，旧文件留到下次启动时清理。失败时恢复原文件
+pub(crate) fn swap_executable(new: &Path, exe: &Path) -> io::Result<()> {
+    let staged = fsutil::sibling_with_suffix(exe, TMP_SUFFIX);
+    fs::copy(new, &staged)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    let old = old_executable(exe);
+    let _ = fs::remove_file(&old);
+    if let Err(e) = fs::rename(exe, &old) {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    if let Err(e) = fs::rename(&staged, exe) {
+        let _ = fs::rename(&old, exe);
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(name: &str) -> Vec<u8> {
+        let platform = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+        serde_json::json!({
+            "version": "9.0.0",
+            "builds": { platform: { "name": name, "size": 3, "sha256": "abc" } },
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    // 测试用密钥对 `{"version":"9.0.0"}` 的签名
+    const TEST_PUBLIC_KEY: &str = "RWQBAgMEBQYHCGa/a6Y5wp+PTer+kqyE3+6ryFDtDZRGnbin10QLiUnp";
+    const TEST_MANIFEST: &str = r#"{"version":"9.0.0"}"#;
+    const TEST_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQBAgMEBQYHCGzl1CWUgnFRueF2XZmh9vVEAk91uTkKuzfVJdv9qPCNjBXOfR++FKSAyBmDAcrzjKktYwnSPux0DzZeGCfBRAM=
+trusted comment: timestamp:0\tfile:updater_manifest.json
+TGz1SlW/OIJj+lCr1O+QHWnUm4Qu25uKgutLuSK9Uv5opjQVox6PrEzavAC4WvY/SrXy8R6kJIP8g7WehGGnAA==
+";
+
+    #[test]
+    fn verify_manifest_checks_signature() {
+        let signature = TEST_SIGNATURE.as_bytes();
+        verify_manifest(TEST_MANIFEST.as_bytes(), signature, TEST_PUBLIC_KEY).unwrap();
+        assert!(verify_manifest(br#"{"version":"9.0.1"}"#, signature, TEST_PUBLIC_KEY).is_err());
+        assert!(verify_manifest(TEST_MANIFEST.as_bytes(), b"", TEST_PUBLIC_KEY).is_err());
+        // 换一个公钥
+        let other_key = "RWQBAgMEBQYHCAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        assert!(verify_manifest(TEST_MANIFEST.as_bytes(), signature, other_key).is_err());
+    }
+
+    #[test]
+    fn is_newer_compares_numeric_parts() {
+        assert!(is_newer("0.2.0", "0.1.0"));
+        assert!(is_newer("v0.10.0", "0.9.9"));
+        assert!(is_newer("1.0.0.1", "1.0.0"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0", "0.2.0"));
+    }
+
+    #[test]
+    fn parse_manifest_returns_newer_build() {
+        let update = parse_manifest(&manifest("updater.exe"), "0.1.0").unwrap();
+        assert_eq!(update.map(|u| u.name).as_deref(), Some("updater.exe"));
+        assert_eq!(
+            parse_manifest(&manifest("updater.exe"), "9.0.0").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_manifest_rejects_paths_in_name() {
+        for name in ["../updater.exe", "bin/updater.exe", "C:updater.exe"] {
+            assert!(
+                parse_manifest(&manifest(name), "0.1.0").is_err(),
+                "{name:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// 更新程序的持久状态，保存在 _updater/state.json 中跨次运行使用：
+// 所选的更新通道（即跟随的远程分支），以及用 --to 固定的版本。
+
+use crate::Result;
+use crate::fsutil;
+use crate::remote;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::PathBuf;
+
+pub(crate) const STATE_FILE: &str = "state.json";
+
+// 用 update --to 固定的版本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    // 用户指定的提交或标签
+    pub rev: String,
+    pub commit: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct State {
+    path: PathBuf,
+    // 未选择时使用默认分支
+    pub(crate) channel: Option<String>,
+    pub(crate) pin: Option<Pin>,
+}
+
+impl State {
+    // 文件不存在或损坏时从默认状态开始
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let j = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+            .unwrap_or(Value::Null);
+        // 固定的 commit 会直接作为更新目标，格式不对时忽略
+        let pin = match (j["pin"]["rev"].as_str(), j["pin"]["commit"].as_str()) {
+            (Some(rev), Some(commit)) => remote::commit_id(commit).ok().map(|commit| Pin {
+                rev: rev.to_string(),
+                commit,
+            }),
+            _ => None,
+        };
+        State {
+            path,
+            channel: j["channel"].as_str().map(str::to_string),
+            pin,
+        }
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        let j = json!({
+            "channel": self.channel,
+            "pin": self.pin.as_ref().map(|pin| json!({ "rev": pin.rev, "commit": pin.commit })),
+        });
+        fsutil::write_atomic(&self.path, serde_json::to_string_pretty(&j)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+// 分支、标签与提交名会拼进各镜像的地址，只允许其中常见的字符
+pub(crate) fn validate_rev(rev: &str) -> Result<()> {
+    let valid = !rev.is_empty()
+        && !rev.starts_with(['-', '/', '.'])
+        && !rev.ends_with(['/', '.'])
+        && !rev.contains("..")
+        && rev
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("无效的分支、标签或提交名: {rev:?}").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rev_accepts_branches_tags_and_commits() {
+        for rev in ["master", "v1.4.2", "feature/new-ui", "dev_2", "0123abcd"] {
+            assert!(validate_rev(rev).is_ok(), "{rev:?}");
+        }
+    }
+
+    #[test]
+    fn validate_rev_rejects_unsafe_names() {
+        for rev in [
+            "", "-x", "/x", ".x", "x/", "x.", "a..b", "../x", "a b", "a;b", "a:b", "a?b", "a~1",
+        ] {
+            assert!(validate_rev(rev).is_err(), "{rev:?}");
+        }
+    }
+
+    #[test]
+    fn save_and_load_pin() {
+        let path = std::env::temp_dir().join(format!("krdove-state-{}.json", std::process::id()));
+        let pin = Pin {
+            rev: "v1.4.2".to_string(),
+            commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+        };
+        let mut state = State::load(path.clone());
+        state.channel = Some("dev".to_string());
+        state.pin = Some(pin.clone());
+        state.save().unwrap();
+        let loaded = State::load(path.clone());
+        assert_eq!(loaded.channel.as_deref(), Some("dev"));
+        assert_eq!(loaded.pin, Some(pin));
+
+        // 固定的 commit 格式不对时忽略
+        fs::write(&path, r#"{ "pin": { "rev": "x", "commit": "../../x" } }"#).unwrap();
+        assert_eq!(State::load(path.clone()).pin, None);
+        let _ = fs::remove_file(&path);
+    }
+}
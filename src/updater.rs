@@ -0,0 +1,1134 @@
+use crate::Result;
+use crate::assets::{
+    self, ASSETS_DIR, ASSETS_INDEX, AssetEntry, AssetPlan, AssetReport, TRASHED_DIR, file_size,
+};
+use crate::backup::{self, BACKUPS_DIR, Backup, BackupInfo};
+use crate::config::Config;
+use crate::event::Event;
+use crate::fsutil;
+use crate::hash;
+use crate::mirrors::{self, ProbeResult};
+use crate::remote::{self, CommitInfo, DEFAULT_BRANCH, DiffAction, DiffRecord, RemoteSource};
+use crate::scores::{MirrorScore, MirrorScores, SCORES_FILE};
+use crate::selfupdate::{
+    self, MANIFEST_NAME, SELF_UPDATE_DIR, SIGNATURE_NAME, SelfUpdate, UPDATER_RELEASE,
+};
+use crate::state::{self, Pin, STATE_FILE, State};
+use crate::version::{self, VERSION_FILE};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const LOCAL_COMMIT_FILE: &str = "current_version_commit_hash.txt";
+const ORIGINAL_COMMIT_FILE: &str = "origin_version_commit_hash.txt";
+// 更新程序自身的状态目录
+pub(crate) const STATE_DIR: &str = "_updater";
+// 暂存目录：_updater/staging/<目标 commit>/{code,assets}
+const STAGING_DIR: &str = "staging";
+const STAGED_CODE_DIR: &str = "code";
+const STAGED_ASSETS_DIR: &str = "assets";
+// 更新程序清单只有几百字节，超出这个大小的响应不可能是清单
+const MAX_MANIFEST_SIZE: u64 = 64 * 1024;
+
+type EventHandler = Box<dyn Fn(Event) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkingMode {
+    // 从本地记录的版本差分更新
+    Normal,
+    // 从原始发布版本差分，重新下载此后变动过的所有代码文件
+    Fix,
+    // 对照远程文件树逐个校验本地代码文件，只下载缺失或内容不符的文件
+    Repair,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub local_commit: String,
+    pub remote_commit: String,
+}
+
+impl CheckResult {
+    pub fn is_up_to_date(&self) -> bool {
+        self.local_commit == self.remote_commit
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdatePlan {
+    pub mode: WorkingMode,
+    pub from_commit: String,
+    pub to_commit: String,
+    // 按时间顺序排列的提交信息
+    pub messages: Vec<String>,
+    pub files: Vec<DiffRecord>,
+    // 目标版本中各文件的 git blob 哈希，下载后据此校验；缺失的文件不校验
+    pub blobs: HashMap<String, String>,
+    // 修复模式下发现的、本地存在但不在远程文件树中的文件。只报告，不删除
+    pub untracked: Vec<String>,
+}
+
+impl UpdatePlan {
+    pub fn is_empty(&self) -> bool {
+        self.from_commit == self.to_commit
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileFailure {
+    pub file: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ApplyReport {
+    pub failed_files: Vec<FileFailure>,
+    // 代码文件全部更新成功后才会同步资源
+    pub assets: Option<AssetReport>,
+}
+
+impl ApplyReport {
+    // 代码与资源都更新成功，本地版本记录已写回
+    pub fn is_complete(&self) -> bool {
+        self.failed_files.is_empty() && self.assets.as_ref().is_some_and(|a| a.is_complete())
+    }
+}
+
+// 只读的安装完整性检查结果。资源路径带 _assets/ 前缀
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    // 检查所依据的本地版本
+    pub commit: String,
+    pub missing: Vec<String>,
+    // 内容与远程版本或资源索引中的哈希不符
+    pub modified: Vec<String>,
+    // 不属于该版本或资源索引的多余文件
+    pub extra: Vec<String>,
+    // 大小与资源索引不符的资源
+    pub size_mismatched: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.modified.is_empty()
+            && self.extra.is_empty()
+            && self.size_mismatched.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum AssetProblem {
+    Missing,
+    SizeMismatched,
+    Modified,
+}
+
+pub struct Updater {
+    root: PathBuf,
+    sources: Vec<Arc<dyn RemoteSource>>,
+    scores: MirrorScores,
+    state: State,
+    // 保留最近几次更新的备份
+    keep_backups: usize,
+    on_event: Option<EventHandler>,
+    // 只读模式：不保存镜像记录，拒绝应用更新
+    read_only: bool,
+}
+
+impl Updater {
+    // root 为游戏目录（即 Kingdom Rush 目录）
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let scores = MirrorScores::load(root.join(STATE_DIR).join(SCORES_FILE));
+        let state = State::load(root.join(STATE_DIR).join(STATE_FILE));
+        Updater {
+            root,
+            sources: Vec::new(),
+            scores,
+            state,
+            keep_backups: Config::default().backups,
+            on_event: None,
+            read_only: false,
+        }
+        .with_sources(Config::default().sources().expect("内置镜像配置无效"))
+    }
+
+    pub fn with_config(mut self, config: &Config) -> Result<Self> {
+        self.keep_backups = config.backups;
+        Ok(self.with_sources(config.sources()?))
+    }
+
+    // 替换默认的远程源。按给定顺序尝试，但历史上频繁失败的镜像会被排到后面
+    pub fn with_sources(mut self, mut sources: Vec<Arc<dyn RemoteSource>>) -> Self {
+        sources.sort_by_key(|source| self.scores.sort_key(source.name()));
+        self.sources = sources;
+        self
+    }
+
+    pub fn on_event(mut self, handler: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Box::new(handler));
+        self
+    }
+
+    // 预演（--dry-run）时使用，保证不写入游戏目录与更新程序状态
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    // 并发探测所有远程源，之后的操作按探测结果与历史记录从好到差依次尝试。
+    // 返回排序后的探测结果
+    pub fn rank_mirrors(&mut self) -> Vec<ProbeResult> {
+        let results = mirrors::probe_sources(&self.sources, &self.scores, self.channel());
+        let (sources, results) =
+            mirrors::rank(std::mem::take(&mut self.sources), results, &self.scores);
+        self.sources = sources;
+        self.persist_scores();
+        results
+    }
+
+    // 清理上次运行被中断时残留的临时文件，返回被删除的文件
+    pub fn clean_temporaries(&self) -> Result<Vec<PathBuf>> {
+        Ok(fsutil::clean_temporaries(&self.root)?)
+    }
+
+    // 某个镜像在历次运行中积累的可靠性记录
+    pub fn mirror_score(&self, mirror: &str) -> MirrorScore {
+        self.scores.get(mirror)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn local_commit(&self) -> io::Result<String> {
+        read_commit_file(&self.root.join(LOCAL_COMMIT_FILE))
+    }
+
+    pub fn original_commit(&self) -> io::Result<String> {
+        read_commit_file(&self.root.join(ORIGINAL_COMMIT_FILE))
+    }
+
+    // 本地 version.lua 中的游戏版本号。文件缺失或无法执行时返回 None
+    pub fn version_id(&self) -> Option<String> {
+        let content = fs::read(self.root.join(VERSION_FILE)).ok()?;
+        version::parse_version_id(&content).ok().flatten()
+    }
+
+    // 本地已知的各版本的游戏版本号：当前安装的版本，以及备份中记录的更新前后的版本
+    pub fn known_versions(&self) -> HashMap<String, String> {
+        let mut versions = HashMap::new();
+        for backup in self.backups().into_iter().rev() {
+            if let Some(id) = backup.from_version {
+                versions.insert(backup.from_commit, id);
+            }
+            if let Some(id) = backup.to_version {
+                versions.insert(backup.to_commit, id);
+            }
+        }
+        if let (Ok(commit), Some(id)) = (self.local_commit(), self.version_id()) {
+            versions.insert(commit, id);
+        }
+        versions
+    }
+
+    // rev 版本的 version.lua 中的游戏版本号。已知 blob 哈希时先校验内容再执行
+    pub fn remote_version_id(
+        &self,
+        rev: &str,
+        expected_blob: Option<&str>,
+    ) -> Result<Option<String>> {
+        // 版本号只用于显示，失败时不逐次提示
+        let content = remote::with_retry(
+            &self.sources,
+            &self.scores,
+            |source| {
+                let content = source.fetch_file(rev, VERSION_FILE)?;
+                check_blob(&content, expected_blob)?;
+                Ok(content)
+            },
+            |_, _| {},
+        );
+        self.persist_scores();
+        version::parse_version_id(&content?)
+    }
+
+    // 当前跟随的更新通道，即远程分支名
+    pub fn channel(&self) -> &str {
+        self.state.channel.as_deref().unwrap_or(DEFAULT_BRANCH)
+    }
+
+    // 切换更新通道并保存，返回新通道的最新版本。
+    // 先解析一次新通道以确认分支存在；之后的更新从本地已安装的版本差分到新通道
+    pub fn set_channel(&mut self, channel: &str) -> Result<String> {
+        state::validate_rev(channel)?;
+        if self.read_only {
+            return Err("只读模式下不能切换通道".into());
+        }
+        let head = self.head_commit(channel)?;
+        self.state.channel = Some(channel.to_string());
+        self.state.save()?;
+        Ok(head)
+    }
+
+    // 用 update --to 固定的版本
+    pub fn pin(&self) -> Option<&Pin> {
+        self.state.pin.as_ref()
+    }
+
+    // 记录或解除固定版本
+    pub fn set_pin(&mut self, pin: Option<Pin>) -> Result<()> {
+        if self.read_only {
+            return Err("只读模式下不能修改固定版本".into());
+        }
+        self.state.pin = pin;
+        self.state.save()
+    }
+
+    // 当前通道的最新版本
+    pub fn remote_commit(&self) -> Result<String> {
+        self.head_commit(self.channel())
+    }
+
+    // 当前通道最近的 limit 个提交，从新到旧
+    pub fn history(&self, limit: usize) -> Result<Vec<CommitInfo>> {
+        let result = remote::with_retry(
+            &self.sources,
+            &self.scores,
+            |source| source.commits(self.channel(), limit),
+            |source, e| {
+                self.emit(Event::HeadRetry {
+                    mirror: source.name().to_string(),
+                    reason: e.to_string(),
+                })
+            },
+        );
+        self.persist_scores();
+        result
+    }
+
+    // 把提交、标签或分支名解析为 commit
+    pub fn resolve(&self, rev: &str) -> Result<String> {
+        state::validate_rev(rev)?;
+        self.resolve_with(|source| source.resolve_commit(rev))
+    }
+
+    fn head_commit(&self, branch: &str) -> Result<String> {
+        self.resolve_with(|source| source.head_commit(branch))
+    }
+
+    fn resolve_with(
+        &self,
+        mut op: impl FnMut(&dyn RemoteSource) -> Result<String>,
+    ) -> Result<String> {
+        // 第三方实现的远程源也要经过同样的校验
+        let op = |source: &dyn RemoteSource| op(source).and_then(|sha| remote::commit_id(&sha));
+        let result = remote::with_retry(&self.sources, &self.scores, op, |source, e| {
+            self.emit(Event::HeadRetry {
+                mirror: source.name().to_string(),
+                reason: e.to_string(),
+            })
+        });
+        self.persist_scores();
+        result
+    }
+
+    pub fn check(&self) -> Result<CheckResult> {
+        Ok(CheckResult {
+            local_commit: self.local_commit()?,
+            remote_commit: self.remote_commit()?,
+        })
+    }
+
+    pub fn plan(&self, mode: WorkingMode) -> Result<UpdatePlan> {
+        let (from_commit, to_commit) = match mode {
+            WorkingMode::Normal => (self.local_commit()?, self.remote_commit()?),
+            WorkingMode::Fix => (self.original_commit()?, self.remote_commit()?),
+            // 修复按当前安装的版本校验，不改变版本
+            WorkingMode::Repair => {
+                let local_commit = self.local_commit()?;
+                (local_commit.clone(), local_commit)
+            }
+        };
+        self.plan_between(mode, from_commit, to_commit)
+    }
+
+    pub fn plan_between(
+        &self,
+        mode: WorkingMode,
+        from_commit: String,
+        to_commit: String,
+    ) -> Result<UpdatePlan> {
+        if mode == WorkingMode::Repair {
+            // 校验结果只能说明本地文件与某个版本是否一致，无法得出跨版本时应删除的文件
+            if from_commit != to_commit {
+                return Err("修复只能针对当前安装的版本".into());
+            }
+            return self.plan_repair(from_commit);
+        }
+        let mut comparison = if from_commit == to_commit {
+            remote::Comparison::default()
+        } else {
+            self.diff(&from_commit, &to_commit)?
+        };
+        // 比较接口只给出合并基础之后目标一侧的改动。本地版本不在目标的历史中时
+        // （切换了通道），还要把本地一侧自合并基础以来的改动还原
+        if let Some(base) = &comparison.merge_base
+            && *base != from_commit
+        {
+            let local_changes = self.diff(&to_commit, &from_commit)?;
+            comparison.files = revert_divergent(comparison.files, local_changes.files);
+            // 反向比较给出的哈希属于本地一侧，还原的文件按目标版本的文件树校验
+            let tree = self.tree(&to_commit)?;
+            for (_, file) in &comparison.files {
+                if !comparison.blobs.contains_key(file)
+                    && let Some(sha) = tree.get(file)
+                {
+                    comparison.blobs.insert(file.clone(), sha.clone());
+                }
+            }
+        }
+        Ok(UpdatePlan {
+            mode,
+            from_commit,
+            to_commit,
+            messages: comparison.messages,
+            files: comparison.files,
+            blobs: comparison.blobs,
+            untracked: Vec::new(),
+        })
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Result<remote::Comparison> {
+        let comparison = remote::with_retry(
+            &self.sources,
+            &self.scores,
+            |source| source.diff(from, to),
+            |_, _| {},
+        );
+        self.persist_scores();
+        comparison
+    }
+
+    // 获取目标版本的文件树，缺失的文件记为新增，内容不符的记为修改
+    fn plan_repair(&self, commit: String) -> Result<UpdatePlan> {
+        let tree = self.tree(&commit)?;
+        let files = compare_tree(&self.root, &tree);
+        let untracked = untracked_files(&self.root, &tree)?;
+
+        Ok(UpdatePlan {
+            mode: WorkingMode::Repair,
+            from_commit: commit.clone(),
+            to_commit: commit,
+            messages: Vec::new(),
+            files,
+            blobs: tree,
+            untracked,
+        })
+    }
+
+    // 分两步更新：先把目标版本的代码文件与美术资源全部下载到暂存目录，
+    // 全部成功后再一次性移入游戏目录并写回本地版本记录。
+    // 任何下载失败时游戏目录保持原样，已暂存的文件留待下次重试时复用
+    pub fn apply(&self, plan: &UpdatePlan) -> Result<ApplyReport> {
+        if self.read_only {
+            return Err("只读模式下不能应用更新".into());
+        }
+        let staging = self.staging_dir(&plan.to_commit);
+        self.clean_stale_staging(&plan.to_commit);
+
+        let failed_files: Vec<FileFailure> = plan
+            .files
+            .par_iter()
+            .filter(|(action, _)| *action != DiffAction::Removed)
+            .filter_map(|(action, file)| {
+                let expected = plan.blobs.get(file).map(String::as_str);
+                let result = match action {
+                    DiffAction::Renamed { from }
+                        if self.stage_unchanged_rename(&staging, from, file, expected) =>
+                    {
+                        Ok(())
+                    }
+                    _ => self.stage_file(&staging, &plan.to_commit, file, expected),
+                };
+                result.err().map(|e| FileFailure {
+                    file: file.clone(),
+                    error: e.to_string(),
+                })
+            })
+            .collect();
+
+        self.persist_scores();
+
+        if !failed_files.is_empty() {
+            return Ok(ApplyReport {
+                failed_files,
+                assets: None,
+            });
+        }
+
+        // 资源索引本身也可能在本次更新中变化，以目标版本的为准
+        let index_file = Path::new(ASSETS_DIR).join(ASSETS_INDEX);
+        let staged_index = staging.join(STAGED_CODE_DIR).join(&index_file);
+        let assets_index = assets::read_assets_index(if staged_index.exists() {
+            staged_index
+        } else {
+            self.root.join(&index_file)
+        })?;
+        let assets_dir = self.root.join(ASSETS_DIR);
+        let emit = |e| self.emit(e);
+        let batches = assets::plan_downloads(&assets_index, &assets_dir, &emit);
+        let files = assets::download_batches(
+            &batches,
+            &staging.join(STAGED_ASSETS_DIR),
+            &self.sources,
+            &self.scores,
+            &emit,
+        );
+        self.persist_scores();
+
+        let mut report = AssetReport {
+            files,
+            trashed: Vec::new(),
+        };
+        if !report.is_complete() {
+            return Ok(ApplyReport {
+                failed_files,
+                assets: Some(report),
+            });
+        }
+
+        // 全部就绪，移入游戏目录。被覆盖或删除的文件移入以旧版本命名的备份目录
+        let installed_commit = self
+            .local_commit()
+            .unwrap_or_else(|_| plan.from_commit.clone());
+        let installed_version = self.version_id();
+        // 修复时没有需要替换的文件，只清理多余的资源，不产生备份
+        if installed_commit == plan.to_commit && plan.files.is_empty() && report.files.is_empty() {
+            report.trashed = assets::trash_unindexed_assets(
+                &assets_index,
+                &assets_dir,
+                &self.root.join(TRASHED_DIR),
+                &emit,
+            )?;
+            let _ = fs::remove_dir_all(&staging);
+            return Ok(ApplyReport {
+                failed_files,
+                assets: Some(report),
+            });
+        }
+        let mut backup = Backup::begin(
+            &self.backups_dir(),
+            &self.root,
+            &installed_commit,
+            &plan.to_commit,
+        )?;
+        for (action, file) in &plan.files {
+            backup.record(file);
+            if let DiffAction::Renamed { from } = action {
+                backup.record(from);
+            }
+        }
+        for outcome in &report.files {
+            backup.record(&format!("{ASSETS_DIR}/{}", outcome.file));
+        }
+        backup.write_manifest()?;
+
+        let moved = (|| -> Result<Vec<String>> {
+            // 先把所有将被覆盖、删除的文件与改名前的旧路径移入备份，再移入新文件。
+            // 分两遍进行，结果不受列表顺序影响：新增的文件可能正好是另一个文件改名前的路径
+            for (action, file) in &plan.files {
+                backup.save(file)?;
+                if let DiffAction::Renamed { from } = action {
+                    backup.save(from)?;
+                }
+            }
+            for (action, file) in &plan.files {
+                if *action != DiffAction::Removed {
+                    move_into_place(
+                        &staging.join(STAGED_CODE_DIR).join(file),
+                        &self.root.join(file),
+                    )?;
+                }
+            }
+            for outcome in &report.files {
+                backup.save(&format!("{ASSETS_DIR}/{}", outcome.file))?;
+                move_into_place(
+                    &staging.join(STAGED_ASSETS_DIR).join(&outcome.file),
+                    &assets_dir.join(&outcome.file),
+                )?;
+            }
+            assets::trash_unindexed_assets(
+                &assets_index,
+                &assets_dir,
+                &self.root.join(TRASHED_DIR),
+                &emit,
+            )
+        })();
+        // 移动中途失败（例如文件被正在运行的游戏占用）时还原到更新前的状态，
+        // 已移入游戏目录的新文件会被删除，暂存目录中未移动的文件留待下次重试
+        report.trashed = match moved {
+            Ok(trashed) => trashed,
+            Err(e) => {
+                return Err(match self.restore(backup.info()) {
+                    Ok(()) => format!("移动文件失败，已恢复到更新前的版本：{e}").into(),
+                    Err(restore_err) => format!(
+                        "移动文件失败：{e}；恢复原文件也失败：{restore_err}，原文件保存在 {}",
+                        self.backups_dir().join(&backup.info().id).display()
+                    )
+                    .into(),
+                });
+            }
+        };
+        backup.record_trashed(&report.trashed);
+        backup.record_versions(installed_version, self.version_id());
+        backup.write_manifest()?;
+        backup::prune(&self.backups_dir(), self.keep_backups);
+
+        // 写回最新 commit_hash
+        fsutil::write_atomic(
+            &self.root.join(LOCAL_COMMIT_FILE),
+            plan.to_commit.as_bytes(),
+        )?;
+        let _ = fs::remove_dir_all(&staging);
+
+        Ok(ApplyReport {
+            failed_files,
+            assets: Some(report),
+        })
+    }
+
+    // 预演 apply 中的资源同步：按目标版本的资源索引列出需要下载和将移入回收站的资源。
+    // 索引在本次更新中有变化时只把新索引读入内存，不写入任何文件
+    pub fn preview(&self, plan: &UpdatePlan) -> Result<AssetPlan> {
+        let index_file = format!("{ASSETS_DIR}/{ASSETS_INDEX}");
+        let index_changed = plan
+            .files
+            .iter()
+            .any(|(action, file)| *file == index_file && *action != DiffAction::Removed);
+        let assets_index = if index_changed {
+            let expected = plan.blobs.get(&index_file).map(String::as_str);
+            let content = self.fetch_verified(&plan.to_commit, &index_file, expected);
+            self.persist_scores();
+            assets::parse_assets_index(&String::from_utf8(content?)?)?
+        } else {
+            assets::read_assets_index(self.root.join(&index_file))?
+        };
+        assets::preview(&assets_index, &self.root.join(ASSETS_DIR))
+    }
+
+    // 检查安装是否完整：代码文件对照本地记录版本的远程文件树，资源对照资源索引。
+    // 不修改任何文件
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let commit = self.local_commit()?;
+        let tree = self.tree(&commit)?;
+        let mut report = VerifyReport {
+            extra: untracked_files(&self.root, &tree)?,
+            commit,
+            ..Default::default()
+        };
+        for (action, file) in compare_tree(&self.root, &tree) {
+            match action {
+                DiffAction::Added => report.missing.push(file),
+                _ => report.modified.push(file),
+            }
+        }
+
+        let assets_dir = self.root.join(ASSETS_DIR);
+        let assets_index = assets::read_assets_index(assets_dir.join(ASSETS_INDEX))?;
+        let mut problems: Vec<(AssetProblem, String)> = assets_index
+            .par_iter()
+            .filter_map(|(path, entry)| {
+                let fullpath = assets_dir.join(path);
+                let name = format!("{ASSETS_DIR}/{path}");
+                if !fullpath.is_file() {
+                    Some((AssetProblem::Missing, name))
+                } else if file_size(&fullpath) != entry.size {
+                    Some((AssetProblem::SizeMismatched, name))
+                } else if !entry.matches(&fullpath) {
+                    Some((AssetProblem::Modified, name))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        problems.sort();
+        for (kind, name) in problems {
+            match kind {
+                AssetProblem::Missing => report.missing.push(name),
+                AssetProblem::SizeMismatched => report.size_mismatched.push(name),
+                AssetProblem::Modified => report.modified.push(name),
+            }
+        }
+        report.extra.extend(
+            assets::unindexed_assets(&assets_index, &assets_dir)?
+                .into_iter()
+                .map(|file| format!("{ASSETS_DIR}/{file}")),
+        );
+        Ok(report)
+    }
+
+    // 按 _assets/assets_index.lua 下载缺失或大小不符的资源，并清理多余资源
+    pub fn sync_assets(&self) -> Result<AssetReport> {
+        let report =
+            assets::update_assets(&self.root, &self.sources, &self.scores, &|e| self.emit(e));
+        self.persist_scores();
+        report
+    }
+
+    // 查询 updater release 中是否有比 current_version 更新、且适用于当前平台的更新程序。
+    // 还没有发布清单时视为没有新版本，不算作镜像失败；清单签名无效时换下一个镜像。
+    // 没有内置公钥的构建不检查更新
+    pub fn check_self_update(&self, current_version: &str) -> Result<Option<SelfUpdate>> {
+        let Some(public_key) = selfupdate::PUBLIC_KEY else {
+            return Ok(None);
+        };
+        let read = |source: &dyn RemoteSource, name: &str| -> Result<Vec<u8>> {
+            let mut content = Vec::new();
+            source
+                .fetch_asset(UPDATER_RELEASE, name, 0)?
+                .reader
+                .take(MAX_MANIFEST_SIZE)
+                .read_to_end(&mut content)?;
+            Ok(content)
+        };
+        let manifest = remote::with_retry(
+            &self.sources,
+            &self.scores,
+            |source| match read(source, MANIFEST_NAME) {
+                Ok(manifest) => {
+                    let signature = read(source, SIGNATURE_NAME)?;
+                    selfupdate::verify_manifest(&manifest, &signature, public_key)?;
+                    Ok(Some(manifest))
+                }
+                Err(e) if remote::is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            },
+            |_, _| {},
+        );
+        self.persist_scores();
+        match manifest? {
+            Some(manifest) => selfupdate::parse_manifest(&manifest, current_version),
+            None => Ok(None),
+        }
+    }
+
+    // 下载并校验新版更新程序，替换 exe。原文件改名为 <exe>.old，由 clean_self_update 清理
+    pub fn install_self_update(&self, update: &SelfUpdate, exe: &Path) -> Result<()> {
+        if self.read_only {
+            return Err("只读模式下不能更新更新程序".into());
+        }
+        let dir = self.root.join(STATE_DIR).join(SELF_UPDATE_DIR);
+        let batches = HashMap::from([(
+            UPDATER_RELEASE.to_string(),
+            vec![(
+                update.name.clone(),
+                AssetEntry {
+                    size: update.size,
+                    sha256: Some(update.sha256.clone()),
+                },
+            )],
+        )]);
+        let emit = |e| self.emit(e);
+        let outcomes = assets::download_batches(&batches, &dir, &self.sources, &self.scores, &emit);
+        self.persist_scores();
+        if let Some(error) = outcomes.into_iter().find_map(|outcome| outcome.error) {
+            return Err(error.into());
+        }
+        selfupdate::swap_executable(&dir.join(&update.name), exe)?;
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    // 删除上次自我更新留下的旧可执行文件。旧进程可能尚未退出，删除失败时留待下次
+    pub fn clean_self_update(&self, exe: &Path) {
+        let _ = fs::remove_file(selfupdate::old_executable(exe));
+    }
+
+    // 所有备份，包括修复产生的，最新的在前
+    pub fn backups(&self) -> Vec<BackupInfo> {
+        backup::list(&self.backups_dir())
+    }
+
+    // 从 commit 回滚时使用的备份；修复产生的备份不用于回滚
+    pub fn rollback_backup(&self, commit: &str) -> Option<BackupInfo> {
+        backup::find_rollback(&self.backups_dir(), commit)
+    }
+
+    // 回滚最近一次更新：还原被覆盖或删除的文件，删除新增的文件，
+    // 并把本地版本记录改回更新前的版本。返回所用的备份
+    pub fn rollback(&self) -> Result<BackupInfo> {
+        if self.read_only {
+            return Err("只读模式下不能回滚".into());
+        }
+        let local_commit = self.local_commit()?;
+        let info = self
+            .rollback_backup(&local_commit)
+            .ok_or_else(|| format!("没有找到从当前版本 {local_commit} 回滚的备份"))?;
+        self.restore(&info)?;
+        fsutil::write_atomic(
+            &self.root.join(LOCAL_COMMIT_FILE),
+            info.from_commit.as_bytes(),
+        )?;
+        Ok(info)
+    }
+
+    fn tree(&self, rev: &str) -> Result<HashMap<String, String>> {
+        let tree = remote::with_retry(
+            &self.sources,
+            &self.scores,
+            |source| source.tree(rev),
+            |_, _| {},
+        );
+        self.persist_scores();
+        tree
+    }
+
+    fn restore(&self, info: &BackupInfo) -> Result<()> {
+        backup::restore(
+            &self.backups_dir(),
+            &self.root,
+            &self.root.join(ASSETS_DIR),
+            &self.root.join(TRASHED_DIR),
+            info,
+        )
+    }
+
+    fn backups_dir(&self) -> PathBuf {
+        self.root.join(STATE_DIR).join(BACKUPS_DIR)
+    }
+
+    fn staging_dir(&self, commit: &str) -> PathBuf {
+        self.root.join(STATE_DIR).join(STAGING_DIR).join(commit)
+    }
+
+    // 删除为其它版本准备的暂存目录
+    fn clean_stale_staging(&self, commit: &str) {
+        let Ok(entries) = fs::read_dir(self.root.join(STATE_DIR).join(STAGING_DIR)) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if entry.file_name() != commit {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+
+    // 把 rev 版本的代码文件下载到暂存目录。暂存文件是原子写入且校验过的，存在即完整，可直接复用
+    fn stage_file(
+        &self,
+        staging: &Path,
+        rev: &str,
+        file: &str,
+        expected_blob: Option<&str>,
+    ) -> Result<()> {
+        let path = staging.join(STAGED_CODE_DIR).join(file);
+        if path.exists() {
+            return Ok(());
+        }
+        self.emit(Event::FileStarted {
+            file: file.to_string(),
+        });
+        let result = self
+            .fetch_verified(rev, file, expected_blob)
+            .and_then(|content| Ok(fsutil::write_atomic(&path, &content)?));
+        self.emit(Event::FileFinished {
+            file: file.to_string(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
+
+    // 下载 rev 版本的代码文件。给出 blob 哈希时校验内容，不符（镜像缓存过期、错误页面等）则换下一个镜像
+    fn fetch_verified(
+        &self,
+        rev: &str,
+        file: &str,
+        expected_blob: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        remote::with_retry(
+            &self.sources,
+            &self.scores,
+            |source| {
+                let content = source.fetch_file(rev, file)?;
+                check_blob(&content, expected_blob)?;
+                Ok(content)
+            },
+            |source, e| {
+                self.emit(Event::FileRetry {
+                    file: file.to_string(),
+                    mirror: source.name().to_string(),
+                    reason: e.to_string(),
+                })
+            },
+        )
+    }
+
+    // 仅改名、内容未变的文件直接用本地旧文件暂存，无需下载。
+    // 旧文件随后在移入阶段进入备份，效果等同于移动。没有 blob 哈希时无法判断，返回 false
+    fn stage_unchanged_rename(
+        &self,
+        staging: &Path,
+        from: &str,
+        file: &str,
+        expected_blob: Option<&str>,
+    ) -> bool {
+        let Some(expected) = expected_blob else {
+            return false;
+        };
+        let path = staging.join(STAGED_CODE_DIR).join(file);
+        if path.exists() {
+            return true;
+        }
+        match fs::read(self.root.join(from)) {
+            Ok(content) if hash::git_blob_sha1(&content).eq_ignore_ascii_case(expected) => {
+                fsutil::write_atomic(&path, &content).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    // 镜像记录只用于优化下次的镜像顺序，保存失败不影响更新
+    fn persist_scores(&self) {
+        if self.read_only {
+            return;
+        }
+        let _ = self.scores.save();
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(handler) = &self.on_event {
+            handler(event);
+        }
+    }
+}
+
+// 暂存目录与游戏目录在同一文件系统中，改名即可完成移动
+fn move_into_place(staged: &Path, target: &Path) -> io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(staged, target)
+}
+
+// 把本地一侧自合并基础以来的改动（local_changes）转换为还原操作，并入目标一侧的改动。
+// 两侧都动过的文件以目标一侧为准，其余文件在目标版本中都与合并基础相同
+fn revert_divergent(mut files: Vec<DiffRecord>, local_changes: Vec<DiffRecord>) -> Vec<DiffRecord> {
+    let mut touched: HashSet<String> = HashSet::new();
+    for (action, file) in &files {
+        touched.insert(file.clone());
+        if let DiffAction::Renamed { from } = action {
+            touched.insert(from.clone());
+        }
+    }
+    let mut revert = |action: DiffAction, file: String| {
+        if touched.insert(file.clone()) {
+            files.push((action, file));
+        }
+    };
+    for (action, file) in local_changes {
+        match action {
+            DiffAction::Added => revert(DiffAction::Removed, file),
+            DiffAction::Modified => revert(DiffAction::Modified, file),
+            DiffAction::Removed => revert(DiffAction::Added, file),
+            DiffAction::Renamed { from } => {
+                revert(DiffAction::Removed, file);
+                revert(DiffAction::Added, from);
+            }
+        }
+    }
+    files
+}
+
+// 并行计算本地文件的 blob 哈希并与文件树比对，缺失的记为新增，内容不符的记为修改
+fn compare_tree(root: &Path, tree: &HashMap<String, String>) -> Vec<DiffRecord> {
+    let mut files: Vec<DiffRecord> = tree
+        .par_iter()
+        .filter_map(|(path, sha)| match fs::read(root.join(path)) {
+            Err(_) => Some((DiffAction::Added, path.clone())),
+            Ok(content) if !hash::git_blob_sha1(&content).eq_ignore_ascii_case(sha) => {
+                Some((DiffAction::Modified, path.clone()))
+            }
+            Ok(_) => None,
+        })
+        .collect();
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    files
+}
+
+// 在文件树涉及的顶层目录中找出不属于文件树的文件。
+// 游戏目录下还有存档、资源等不受版本管理的内容，因此不检查顶层文件和资源目录
+fn untracked_files(root: &Path, tree: &HashMap<String, String>) -> io::Result<Vec<String>> {
+    let mut dirs: Vec<&str> = tree
+        .keys()
+        .filter_map(|path| path.split_once('/').map(|(dir, _)| dir))
+        .filter(|dir| *dir != ASSETS_DIR && *dir != STATE_DIR)
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+
+    let mut untracked = Vec::new();
+    let mut stack: Vec<PathBuf> = dirs.iter().map(|dir| root.join(dir)).collect();
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let Ok(relpath) = path.strip_prefix(root) else {
+                continue;
+            };
+            let relpath = relpath
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !tree.contains_key(&relpath) {
+                untracked.push(relpath);
+            }
+        }
+    }
+    untracked.sort();
+    Ok(untracked)
+}
+
+// 下载的内容与比较接口或文件树给出的 blob 哈希不符时报错，没有哈希时不校验
+fn check_blob(content: &[u8], expected_blob: Option<&str>) -> Result<()> {
+    if let Some(expected) = expected_blob {
+        let actual = hash::git_blob_sha1(content);
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(format!("内容校验失败：期望 blob {}，实际 {}", expected, actual).into());
+        }
+    }
+    Ok(())
+}
+
+// 版本记录同样会用作备份目录名，格式不对时视为损坏
+fn read_commit_file(path: &Path) -> io::Result<String> {
+    let content = fs::read_to_string(path)?;
+    remote::commit_id(&content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_blob_compares_git_blob_hash() {
+        // git hash-object 对 "hello\n" 的结果
+        let blob = "ce013625030ba8dba906f756967f9e9ca394464a";
+        assert!(check_blob(b"hello\n", Some(blob)).is_ok());
+        assert!(check_blob(b"hello\n", Some(&blob.to_uppercase())).is_ok());
+        assert!(check_blob(b"hello", Some(blob)).is_err());
+        assert!(check_blob(b"anything", None).is_ok());
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("krdove-updater-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn tree(files: &[(&str, &str)]) -> HashMap<String, String> {
+        files
+            .iter()
+            .map(|(path, content)| (path.to_string(), hash::git_blob_sha1(content.as_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn compare_tree_finds_missing_and_modified_files() {
+        let root = temp_dir("compare-tree");
+        write(&root, "lua/same.lua", "same");
+        write(&root, "lua/changed.lua", "local edit");
+        let tree = tree(&[
+            ("lua/same.lua", "same"),
+            ("lua/changed.lua", "remote"),
+            ("lua/missing.lua", "missing"),
+        ]);
+        assert_eq!(
+            compare_tree(&root, &tree),
+            vec![
+                (DiffAction::Modified, "lua/changed.lua".to_string()),
+                (DiffAction::Added, "lua/missing.lua".to_string()),
+            ]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn untracked_files_lists_extra_files_in_tracked_dirs() {
+        let root = temp_dir("untracked");
+        write(&root, "lua/a.lua", "a");
+        write(&root, "lua/sub/stale.lua", "stale");
+        write(&root, "lua/extra.lua", "extra");
+        // 不在文件树涉及的目录中的文件与更新程序自己的目录不检查
+        write(&root, "saves/slot1.lua", "save");
+        write(&root, "_updater/state.json", "{}");
+        let tree = tree(&[("lua/a.lua", "a"), ("main.lua", "main")]);
+        assert_eq!(
+            untracked_files(&root, &tree).unwrap(),
+            ["lua/extra.lua", "lua/sub/stale.lua"]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    fn record(action: DiffAction, file: &str) -> DiffRecord {
+        (action, file.to_string())
+    }
+
+    fn renamed(from: &str, to: &str) -> DiffRecord {
+        record(
+            DiffAction::Renamed {
+                from: from.to_string(),
+            },
+            to,
+        )
+    }
+
+    #[test]
+    fn revert_divergent_reverses_local_changes() {
+        let files = vec![
+            record(DiffAction::Modified, "lua/both.lua"),
+            renamed("lua/old.lua", "lua/new.lua"),
+        ];
+        let local_changes = vec![
+            record(DiffAction::Added, "lua/local_added.lua"),
+            record(DiffAction::Modified, "lua/local_modified.lua"),
+            record(DiffAction::Removed, "lua/local_removed.lua"),
+            renamed("lua/local_from.lua", "lua/local_to.lua"),
+            // 两侧都动过的文件以目标一侧为准
+            record(DiffAction::Modified, "lua/both.lua"),
+            record(DiffAction::Added, "lua/old.lua"),
+        ];
+        assert_eq!(
+            revert_divergent(files, local_changes),
+            vec![
+                record(DiffAction::Modified, "lua/both.lua"),
+                renamed("lua/old.lua", "lua/new.lua"),
+                record(DiffAction::Removed, "lua/local_added.lua"),
+                record(DiffAction::Modified, "lua/local_modified.lua"),
+                record(DiffAction::Added, "lua/local_removed.lua"),
+                record(DiffAction::Removed, "lua/local_to.lua"),
+                record(DiffAction::Added, "lua/local_from.lua"),
+            ]
+        );
+    }
+}
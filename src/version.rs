@@ -0,0 +1,109 @@
+// 游戏的版本号。游戏仓库在 version.lua 中维护 version.id，例如：
+//
+//   local version = {}
+//   version.id = "1.4.2"
+//   return version
+//
+// 这里用内嵌的 Lua 运行时执行该文件取得版本号，而不是按文本匹配。
+
+use crate::Result;
+use mlua::{ChunkMode, HookTriggers, Lua, LuaOptions, StdLib};
+use std::cell::Cell;
+
+pub const VERSION_FILE: &str = "version.lua";
+
+// 文件来自远程，执行时只提供 string、table、math，并限制内存与执行的指令数，
+// 避免读写本地文件或陷入死循环
+const MEMORY_LIMIT: usize = 4 * 1024 * 1024;
+const HOOK_INTERVAL: u32 = 10_000;
+const MAX_INSTRUCTIONS: u32 = 1_000_000;
+
+// 创建执行远程 Lua 文件用的受限运行时：没有 io、os 等库，内存与指令数有上限。
+// name 为文件名，用于错误信息
+pub(crate) fn sandbox(
+    name: &'static str,
+    memory_limit: usize,
+    max_instructions: u32,
+) -> Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::STRING | StdLib::TABLE | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    lua.set_memory_limit(memory_limit)?;
+    // 基础库中仍有可以读取本地文件的函数，以及会写标准输出、绕过内存限制统计的函数
+    for function in [
+        "dofile",
+        "loadfile",
+        "load",
+        "require",
+        "print",
+        "collectgarbage",
+    ] {
+        lua.globals().set(function, mlua::Nil)?;
+    }
+    let executed = Cell::new(0u32);
+    lua.set_hook(
+        HookTriggers::every_nth_instruction(HOOK_INTERVAL),
+        move |_, _| {
+            executed.set(executed.get() + HOOK_INTERVAL);
+            if executed.get() > max_instructions {
+                return Err(mlua::Error::RuntimeError(format!("{name} 执行时间过长")));
+            }
+            Ok(())
+        },
+    )?;
+    Ok(lua)
+}
+
+// 优先取文件返回的表，否则取全局表 version。没有 id 字段时返回 None
+pub(crate) fn parse_version_id(content: &[u8]) -> Result<Option<String>> {
+    let lua = sandbox(VERSION_FILE, MEMORY_LIMIT, MAX_INSTRUCTIONS)?;
+    let globals = lua.globals();
+    let table = match lua
+        .load(content)
+        .set_mode(ChunkMode::Text)
+        .eval::<mlua::Value>()?
+    {
+        mlua::Value::Table(table) => Some(table),
+        _ => globals.get::<_, Option<mlua::Table>>("version")?,
+    };
+    let Some(table) = table else {
+        return Ok(None);
+    };
+    Ok(table.get::<_, Option<String>>("id")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_returned_table_or_global() {
+        let returned = b"local version = {}\nversion.id = \"1.4.2\"\nreturn version\n";
+        assert_eq!(
+            parse_version_id(returned).unwrap().as_deref(),
+            Some("1.4.2")
+        );
+        let global = b"version = { id = \"1.5.0\" }";
+        assert_eq!(parse_version_id(global).unwrap().as_deref(), Some("1.5.0"));
+        assert_eq!(parse_version_id(b"return {}").unwrap(), None);
+    }
+
+    #[test]
+    fn has_no_file_access() {
+        for chunk in [
+            "return { id = io.read() }",
+            "return { id = os.getenv('HOME') }",
+            "return dofile('version.lua')",
+            "print('--json') return {}",
+            "collectgarbage('stop') return {}",
+        ] {
+            assert!(parse_version_id(chunk.as_bytes()).is_err(), "{chunk}");
+        }
+    }
+
+    #[test]
+    fn stops_endless_loops() {
+        assert!(parse_version_id(b"while true do end").is_err());
+    }
+}
@@ -0,0 +1,379 @@
+// 以本地目录作为远程源，端到端地测试更新、回滚与对远程数据的校验
+
+use kingdom_rush_dove_updater::{LocalSource, Updater, WorkingMode};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const FROM: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+const TO: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+const LOCAL_COMMIT_FILE: &str = "current_version_commit_hash.txt";
+
+struct Fixture {
+    dir: PathBuf,
+}
+
+impl Fixture {
+    // 游戏目录位于 FROM 版本；远程从 FROM 到 TO 修改、新增、删除各一个文件
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("krdove-update-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let fixture = Fixture { dir };
+        fixture.write_game(LOCAL_COMMIT_FILE, FROM);
+        fixture.write_game("lua/a.lua", "old");
+        fixture.write_game("lua/removed.lua", "removed");
+        fixture.write_game("_assets/assets_index.lua", "return {}");
+        fixture.write_remote("heads/master", TO);
+        fixture.write_comparison(json!([
+            { "filename": "lua/a.lua", "status": "modified" },
+            { "filename": "lua/sub/x.lua", "status": "added" },
+            { "filename": "lua/removed.lua", "status": "removed" },
+        ]));
+        fixture.write_remote(&format!("raw/{TO}/lua/a.lua"), "new");
+        fixture.write_remote(&format!("raw/{TO}/lua/sub/x.lua"), "x");
+        fixture
+    }
+
+    fn game(&self) -> PathBuf {
+        self.dir.join("game")
+    }
+
+    fn updater(&self) -> Updater {
+        Updater::new(self.game())
+            .with_sources(vec![Arc::new(LocalSource::new(self.dir.join("remote")))])
+    }
+
+    fn write_game(&self, path: &str, content: &str) {
+        write(&self.game().join(path), content);
+    }
+
+    fn write_remote(&self, path: &str, content: &str) {
+        write(&self.dir.join("remote").join(path), content);
+    }
+
+    fn write_comparison(&self, files: serde_json::Value) {
+        let comparison = json!({
+            "commits": [{ "sha": TO, "commit": { "message": "update" } }],
+            "files": files,
+        });
+        self.write_remote(
+            &format!("compare/{FROM}...{TO}.json"),
+            &comparison.to_string(),
+        );
+    }
+
+    // 按给出的文件内容写入 rev 版本的文件树
+    fn write_tree(&self, rev: &str, files: &[(&str, &str)]) {
+        let tree: Vec<_> = files
+            .iter()
+            .map(|(path, content)| json!({ "path": path, "type": "blob", "sha": git_blob_sha1(content) }))
+            .collect();
+        self.write_remote(
+            &format!("trees/{rev}.json"),
+            &json!({ "tree": tree }).to_string(),
+        );
+    }
+
+    fn read_game(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.game().join(path)).ok()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn git_blob_sha1(content: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()).as_bytes());
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn write(path: &Path, content: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn update_then_rollback() {
+    let fixture = Fixture::new("rollback");
+    let updater = fixture.updater();
+
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    assert_eq!(plan.files.len(), 3);
+    let report = updater.apply(&plan).unwrap();
+    assert!(report.failed_files.is_empty());
+    assert_eq!(fixture.read_game("lua/a.lua").as_deref(), Some("new"));
+    assert_eq!(fixture.read_game("lua/sub/x.lua").as_deref(), Some("x"));
+    assert_eq!(fixture.read_game("lua/removed.lua"), None);
+    assert_eq!(updater.local_commit().unwrap(), TO);
+
+    let backup = updater.rollback().unwrap();
+    assert_eq!(backup.from_commit, FROM);
+    assert_eq!(fixture.read_game("lua/a.lua").as_deref(), Some("old"));
+    assert_eq!(fixture.read_game("lua/sub/x.lua"), None);
+    assert_eq!(
+        fixture.read_game("lua/removed.lua").as_deref(),
+        Some("removed")
+    );
+    assert_eq!(updater.local_commit().unwrap(), FROM);
+}
+
+#[test]
+fn reports_each_downloaded_file() {
+    let fixture = Fixture::new("file-events");
+    fixture.write_comparison(json!([
+        { "filename": "lua/a.lua", "status": "modified" },
+        { "filename": "lua/missing.lua", "status": "added" },
+    ]));
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let updater = fixture.updater().on_event({
+        let events = events.clone();
+        move |event| events.lock().unwrap().push(event.to_json())
+    });
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    updater.apply(&plan).unwrap();
+
+    let mut events = events.lock().unwrap().clone();
+    events.retain(|e| e["event"] == "file_started" || e["event"] == "file_finished");
+    events.sort_by_key(|e| (e["file"].to_string(), e["event"].to_string()));
+    let summary: Vec<_> = events
+        .iter()
+        .map(|e| {
+            (
+                e["event"].as_str().unwrap(),
+                e["file"].as_str().unwrap(),
+                e["ok"].as_bool(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("file_finished", "lua/a.lua", Some(true)),
+            ("file_started", "lua/a.lua", None),
+            ("file_finished", "lua/missing.lua", Some(false)),
+            ("file_started", "lua/missing.lua", None),
+        ]
+    );
+}
+
+#[test]
+fn verifies_files_reverted_when_switching_channels() {
+    let fixture = Fixture::new("switch-channel");
+    // 本地版本不在目标的历史中：本地一侧修改过 lua/local.lua，需要还原为目标版本的内容
+    let base = "cccccccccccccccccccccccccccccccccccccccc";
+    fixture.write_game("lua/local.lua", "local");
+    fixture.write_remote(
+        &format!("compare/{FROM}...{TO}.json"),
+        &json!({
+            "commits": [],
+            "merge_base_commit": { "sha": base },
+            "files": [{ "filename": "lua/a.lua", "status": "modified", "sha": git_blob_sha1("new") }],
+        })
+        .to_string(),
+    );
+    fixture.write_remote(
+        &format!("compare/{TO}...{FROM}.json"),
+        &json!({
+            "commits": [],
+            "merge_base_commit": { "sha": base },
+            "files": [{ "filename": "lua/local.lua", "status": "modified", "sha": git_blob_sha1("local") }],
+        })
+        .to_string(),
+    );
+    fixture.write_tree(TO, &[("lua/a.lua", "new"), ("lua/local.lua", "upstream")]);
+    // 镜像返回的内容与目标版本的文件树不符
+    fixture.write_remote(&format!("raw/{TO}/lua/local.lua"), "stale");
+    let updater = fixture.updater();
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    assert_eq!(
+        plan.blobs.get("lua/local.lua"),
+        Some(&git_blob_sha1("upstream"))
+    );
+    let report = updater.apply(&plan).unwrap();
+    assert_eq!(report.failed_files.len(), 1);
+    assert_eq!(fixture.read_game("lua/local.lua").as_deref(), Some("local"));
+
+    fixture.write_remote(&format!("raw/{TO}/lua/local.lua"), "upstream");
+    assert!(updater.apply(&plan).unwrap().failed_files.is_empty());
+    assert_eq!(
+        fixture.read_game("lua/local.lua").as_deref(),
+        Some("upstream")
+    );
+}
+
+#[test]
+fn remembers_game_versions_of_installed_commits() {
+    let fixture = Fixture::new("versions");
+    fixture.write_game("version.lua", r#"return { id = "1.0" }"#);
+    fixture.write_comparison(json!([{ "filename": "version.lua", "status": "modified" }]));
+    fixture.write_remote(&format!("raw/{TO}/version.lua"), r#"return { id = "2.0" }"#);
+    let updater = fixture.updater();
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    updater.apply(&plan).unwrap();
+
+    let versions = updater.known_versions();
+    assert_eq!(versions.get(FROM).map(String::as_str), Some("1.0"));
+    assert_eq!(versions.get(TO).map(String::as_str), Some("2.0"));
+}
+
+#[test]
+fn renames_file_with_unchanged_content_without_downloading() {
+    let fixture = Fixture::new("rename");
+    fixture.write_comparison(json!([{
+        "filename": "lua/new.lua",
+        "status": "renamed",
+        "previous_filename": "lua/a.lua",
+        "sha": git_blob_sha1("old"),
+    }]));
+    // 远程没有 lua/new.lua 的内容，只能由本地的 lua/a.lua 得到
+    let updater = fixture.updater();
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    assert!(updater.apply(&plan).unwrap().failed_files.is_empty());
+    assert_eq!(fixture.read_game("lua/new.lua").as_deref(), Some("old"));
+    assert_eq!(fixture.read_game("lua/a.lua"), None);
+
+    updater.rollback().unwrap();
+    assert_eq!(fixture.read_game("lua/a.lua").as_deref(), Some("old"));
+    assert_eq!(fixture.read_game("lua/new.lua"), None);
+}
+
+#[test]
+fn rename_source_reused_by_added_file() {
+    let fixture = Fixture::new("rename-reuse");
+    // 新增的文件排在把同一路径改名走的记录之前
+    fixture.write_comparison(json!([
+        { "filename": "lua/a.lua", "status": "added" },
+        { "filename": "lua/new.lua", "status": "renamed", "previous_filename": "lua/a.lua" },
+    ]));
+    fixture.write_remote(&format!("raw/{TO}/lua/a.lua"), "replacement");
+    fixture.write_remote(&format!("raw/{TO}/lua/new.lua"), "old");
+    let updater = fixture.updater();
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    assert!(updater.apply(&plan).unwrap().failed_files.is_empty());
+    assert_eq!(
+        fixture.read_game("lua/a.lua").as_deref(),
+        Some("replacement")
+    );
+    assert_eq!(fixture.read_game("lua/new.lua").as_deref(), Some("old"));
+    assert_eq!(updater.backups()[0].saved, ["lua/a.lua"]);
+
+    updater.rollback().unwrap();
+    assert_eq!(fixture.read_game("lua/a.lua").as_deref(), Some("old"));
+    assert_eq!(fixture.read_game("lua/new.lua"), None);
+}
+
+#[test]
+fn verify_reports_problems_without_changing_files() {
+    let fixture = Fixture::new("verify");
+    fixture.write_tree(
+        FROM,
+        &[
+            ("lua/a.lua", "old"),
+            ("lua/removed.lua", "changed upstream"),
+            ("lua/gone.lua", "gone"),
+        ],
+    );
+    fixture.write_game("lua/extra.lua", "extra");
+    // "hello" 的 SHA-256
+    let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    fixture.write_game(
+        "_assets/assets_index.lua",
+        &format!(
+            r#"return {{
+                ["a.png"] = {{ size = 5, sha256 = "{sha256}" }},
+                ["b.png"] = {{ size = 5 }},
+                ["c.png"] = {{ size = 5 }},
+            }}"#
+        ),
+    );
+    fixture.write_game("_assets/a.png", "hellx");
+    fixture.write_game("_assets/b.png", "hello world");
+    fixture.write_game("_assets/extra.png", "extra");
+
+    let report = fixture.updater().verify().unwrap();
+    assert_eq!(report.commit, FROM);
+    assert_eq!(report.missing, ["lua/gone.lua", "_assets/c.png"]);
+    assert_eq!(report.modified, ["lua/removed.lua", "_assets/a.png"]);
+    assert_eq!(report.size_mismatched, ["_assets/b.png"]);
+    assert_eq!(report.extra, ["lua/extra.lua", "_assets/extra.png"]);
+    assert!(!report.is_clean());
+    assert_eq!(fixture.read_game("_assets/a.png").as_deref(), Some("hellx"));
+}
+
+#[test]
+fn repair_keeps_update_backup_for_rollback() {
+    let fixture = Fixture::new("repair");
+    fixture.write_tree(TO, &[("lua/a.lua", "new"), ("lua/sub/x.lua", "x")]);
+    let updater = fixture.updater();
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    updater.apply(&plan).unwrap();
+
+    // 没有需要修复的文件时不产生备份
+    for _ in 0..4 {
+        let plan = updater.plan(WorkingMode::Repair).unwrap();
+        assert!(plan.files.is_empty());
+        updater.apply(&plan).unwrap();
+    }
+    assert_eq!(updater.backups().len(), 1);
+
+    // 修复产生的备份不用于回滚，也不会挤掉更新的备份
+    for _ in 0..4 {
+        fixture.write_game("lua/a.lua", "corrupted");
+        let plan = updater.plan(WorkingMode::Repair).unwrap();
+        updater.apply(&plan).unwrap();
+        assert_eq!(fixture.read_game("lua/a.lua").as_deref(), Some("new"));
+    }
+    let backup = updater.rollback().unwrap();
+    assert_eq!(
+        (backup.from_commit.as_str(), backup.to_commit.as_str()),
+        (FROM, TO)
+    );
+    assert_eq!(fixture.read_game("lua/a.lua").as_deref(), Some("old"));
+    assert_eq!(updater.local_commit().unwrap(), FROM);
+}
+
+#[test]
+fn dry_run_cannot_apply_or_roll_back() {
+    let fixture = Fixture::new("read-only");
+    let updater = fixture.updater().read_only(true);
+
+    let plan = updater.plan(WorkingMode::Normal).unwrap();
+    assert!(updater.apply(&plan).is_err());
+    assert!(updater.rollback().is_err());
+    assert_eq!(fixture.read_game("lua/a.lua").as_deref(), Some("old"));
+}
+
+#[test]
+fn rejects_unsafe_remote_paths() {
+    for (i, files) in [
+        json!([{ "filename": "../evil.lua", "status": "added" }]),
+        json!([{ "filename": "/tmp/evil.lua", "status": "added" }]),
+        json!([{ "filename": "C:/evil.lua", "status": "added" }]),
+        json!([{ "filename": "D:evil.lua", "status": "added" }]),
+        json!([{ "filename": "lua\\..\\..\\evil.lua", "status": "modified" }]),
+        json!([{ "filename": "lua/a.lua", "status": "renamed", "previous_filename": "../../evil.lua" }]),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let fixture = Fixture::new(&format!("unsafe-{i}"));
+        fixture.write_comparison(files.clone());
+        assert!(fixture.updater().plan(WorkingMode::Normal).is_err(), "{files}");
+        assert_eq!(fixture.read_game(LOCAL_COMMIT_FILE).as_deref(), Some(FROM));
+    }
+}
+
+#[test]
+fn rejects_invalid_remote_commit() {
+    let fixture = Fixture::new("invalid-commit");
+    fixture.write_remote("heads/master", "../../victim");
+    assert!(fixture.updater().plan(WorkingMode::Normal).is_err());
+    assert_eq!(fixture.read_game(LOCAL_COMMIT_FILE).as_deref(), Some(FROM));
+}